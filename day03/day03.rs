@@ -1,92 +1,204 @@
 use std::env;
-use std::i64;
 
-fn solve_part1(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
-
-    let mut num_lines = 1;
-    let accumulated_bits = contents
-        .lines()
-        .map(|line| {
-            line.chars()
-                .map(|c| c.to_digit(10).unwrap())
-                .collect::<Vec<u32>>()
-        })
-        .reduce(|acc, bits| {
-            num_lines += 1;
-            acc.into_iter()
-                .zip(bits)
-                .map(|(acc, bit)| acc + bit)
-                .collect::<Vec<u32>>()
-        })
-        .unwrap()
-        .into_iter()
-        .map(|x| (2 * x) / num_lines)
-        .collect::<Vec<u32>>();
-
-    let num_bits = accumulated_bits.len() - 1;
-    let (gamma, epsilon) =
-        accumulated_bits
-            .into_iter()
-            .enumerate()
-            .fold((0, 0), |(gamma, epsilon), (index, bit)| {
-                (
-                    gamma + 2usize.pow((num_bits - index) as u32) * bit as usize,
-                    epsilon + 2usize.pow((num_bits - index) as u32) * (1 - bit) as usize,
-                )
-            });
-
-    println!("gamma, epsilon: {}, {}", gamma, epsilon);
-
-    gamma * epsilon
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Majority {
+    MostCommon,
+    LeastCommon,
 }
 
-fn solve_part2(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
 
-    let mut oxygen_generator_rating = contents.lines().collect::<Vec<&str>>();
-    let mut split_index = 0;
+impl BitOrder {
+    fn positions(&self, num_bits: usize) -> Vec<usize> {
+        match self {
+            BitOrder::MsbFirst => (0..num_bits).collect(),
+            BitOrder::LsbFirst => (0..num_bits).rev().collect(),
+        }
+    }
+}
 
-    while oxygen_generator_rating.len() != 1 {
-        let (ones, zeroes): (Vec<&str>, Vec<&str>) = oxygen_generator_rating
-            .into_iter()
-            .partition(|&line| line.chars().nth(split_index) == Some('1'));
+#[derive(Debug, Clone, Copy)]
+struct RatingCriteria {
+    majority: Majority,
+    // The bit that is kept when ones and zeroes are equally common
+    tie_break: char,
+    bit_order: BitOrder,
+}
 
-        oxygen_generator_rating = if ones.len() >= zeroes.len() {
-            ones
-        } else {
-            zeroes
+impl RatingCriteria {
+    const OXYGEN_GENERATOR: Self = Self {
+        majority: Majority::MostCommon,
+        tie_break: '1',
+        bit_order: BitOrder::MsbFirst,
+    };
+
+    const CO2_SCRUBBER: Self = Self {
+        majority: Majority::LeastCommon,
+        tie_break: '0',
+        bit_order: BitOrder::MsbFirst,
+    };
+
+    // Returns the selected bit, and whether it was decided by the tie break
+    fn select_bit(&self, ones: usize, zeroes: usize) -> (char, bool) {
+        if ones == zeroes {
+            return (self.tie_break, true);
+        }
+
+        let bit = match self.majority {
+            Majority::MostCommon => {
+                if ones > zeroes {
+                    '1'
+                } else {
+                    '0'
+                }
+            }
+            Majority::LeastCommon => {
+                if ones < zeroes {
+                    '1'
+                } else {
+                    '0'
+                }
+            }
         };
-        split_index += 1;
+        (bit, false)
     }
+}
 
-    let o2_rating = i64::from_str_radix(oxygen_generator_rating[0], 2).unwrap();
+#[derive(Debug)]
+struct Rating {
+    value: usize,
+    // Bit positions, counted from the most significant bit, decided by a tie
+    ties: Vec<usize>,
+}
 
-    let mut co2_scrubber_rating = contents.lines().collect::<Vec<&str>>();
-    split_index = 0;
+fn count_bits(lines: &[&str], index: usize) -> (usize, usize) {
+    lines.iter().fold((0, 0), |(ones, zeroes), line| {
+        match line.chars().nth(index) {
+            Some('1') => (ones + 1, zeroes),
+            _ => (ones, zeroes + 1),
+        }
+    })
+}
 
-    while co2_scrubber_rating.len() != 1 {
-        let (ones, zeroes): (Vec<&str>, Vec<&str>) = co2_scrubber_rating
-            .into_iter()
-            .partition(|&line| line.chars().nth(split_index) == Some('1'));
+fn num_bits(lines: &[&str]) -> usize {
+    lines.iter().map(|line| line.len()).max().unwrap_or(0)
+}
 
-        co2_scrubber_rating = if ones.len() < zeroes.len() {
-            ones
-        } else {
-            zeroes
-        };
-        split_index += 1;
+fn parse_binary(bits: &str) -> usize {
+    usize::from_str_radix(bits, 2).unwrap()
+}
+
+fn common_bits(lines: &[&str], criteria: &RatingCriteria) -> Rating {
+    let mut ties = vec![];
+
+    let bits = (0..num_bits(lines))
+        .map(|index| {
+            let (ones, zeroes) = count_bits(lines, index);
+            let (bit, is_tie) = criteria.select_bit(ones, zeroes);
+            if is_tie {
+                ties.push(index);
+            }
+            bit
+        })
+        .collect::<String>();
+
+    Rating {
+        value: parse_binary(&bits),
+        ties,
+    }
+}
+
+fn find_rating(lines: &[&str], criteria: &RatingCriteria) -> Rating {
+    let mut candidates = lines.to_vec();
+    let mut ties = vec![];
+
+    for index in criteria.bit_order.positions(num_bits(lines)) {
+        if candidates.len() <= 1 {
+            break;
+        }
+
+        // A bit that doesn't occur at all is never the least common one, otherwise
+        // the filtering would end up without any candidates
+        let (ones, zeroes) = count_bits(&candidates, index);
+        if ones == 0 || zeroes == 0 {
+            continue;
+        }
+
+        let (bit, is_tie) = criteria.select_bit(ones, zeroes);
+        if is_tie {
+            ties.push(index);
+        }
+
+        candidates.retain(|line| line.chars().nth(index).unwrap_or('0') == bit);
+    }
+
+    // Anything left after the last bit is a duplicate of the same number
+    Rating {
+        value: parse_binary(candidates[0]),
+        ties,
     }
+}
+
+fn report_ties(name: &str, rating: &Rating) {
+    if !rating.ties.is_empty() {
+        println!("{}: tie at bit positions {:?}", name, rating.ties);
+    }
+}
+
+fn solve_part1(inputfile: String) -> usize {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+    let lines = contents.lines().collect::<Vec<&str>>();
+
+    // Epsilon uses the least common bits, which is the complement of gamma
+    let gamma = common_bits(&lines, &RatingCriteria::OXYGEN_GENERATOR);
+    let mask = (1 << num_bits(&lines)) - 1;
+    let epsilon = Rating {
+        value: !gamma.value & mask,
+        ties: gamma.ties.clone(),
+    };
+
+    report_ties("gamma", &gamma);
+    report_ties("epsilon", &epsilon);
+    println!("gamma, epsilon: {}, {}", gamma.value, epsilon.value);
+
+    gamma.value * epsilon.value
+}
 
-    let co2_rating = i64::from_str_radix(co2_scrubber_rating[0], 2).unwrap();
+fn solve_part2(inputfile: String, bit_order: BitOrder) -> usize {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+    let lines = contents.lines().collect::<Vec<&str>>();
+
+    let o2_criteria = RatingCriteria {
+        bit_order,
+        ..RatingCriteria::OXYGEN_GENERATOR
+    };
+    let co2_criteria = RatingCriteria {
+        bit_order,
+        ..RatingCriteria::CO2_SCRUBBER
+    };
 
-    (o2_rating * co2_rating) as usize
+    let o2_rating = find_rating(&lines, &o2_criteria);
+    let co2_rating = find_rating(&lines, &co2_criteria);
+
+    report_ties("oxygen generator rating", &o2_rating);
+    report_ties("CO2 scrubber rating", &co2_rating);
+
+    o2_rating.value * co2_rating.value
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let bit_order = match args.get(2).map(|arg| arg.as_str()) {
+        Some("lsb") => BitOrder::LsbFirst,
+        _ => BitOrder::MsbFirst,
+    };
+
     println!("Part1: {}", solve_part1(args[1].to_string()));
-    println!("Part2: {}", solve_part2(args[1].to_string()));
+    println!("Part2: {}", solve_part2(args[1].to_string(), bit_order));
 }
@@ -1,11 +1,76 @@
 use itertools::Itertools;
+use std::collections::HashMap;
 use std::env;
 use std::fmt;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WinPattern {
+    Rows,
+    Columns,
+    Diagonals,
+    FourCorners,
+    Blackout,
+}
+
+impl WinPattern {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "rows" => Some(WinPattern::Rows),
+            "columns" => Some(WinPattern::Columns),
+            "diagonals" => Some(WinPattern::Diagonals),
+            "corners" => Some(WinPattern::FourCorners),
+            "blackout" => Some(WinPattern::Blackout),
+            _ => None,
+        }
+    }
+
+    // The cell indices that all need to be marked for the pattern to win
+    fn lines(&self, width: usize, height: usize) -> Vec<Vec<usize>> {
+        if width == 0 || height == 0 {
+            return vec![];
+        }
+
+        match self {
+            WinPattern::Rows => (0..height)
+                .map(|row| (0..width).map(|column| row * width + column).collect())
+                .collect(),
+            WinPattern::Columns => (0..width)
+                .map(|column| (0..height).map(|row| row * width + column).collect())
+                .collect(),
+            WinPattern::Diagonals => {
+                if width != height {
+                    return vec![];
+                }
+                vec![
+                    (0..width).map(|index| index * width + index).collect(),
+                    (0..width)
+                        .map(|index| index * width + (width - 1 - index))
+                        .collect(),
+                ]
+            }
+            WinPattern::FourCorners => {
+                vec![vec![0, width - 1, (height - 1) * width, height * width - 1]
+                    .into_iter()
+                    .unique()
+                    .collect()]
+            }
+            WinPattern::Blackout => vec![(0..width * height).collect()],
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Board {
     numbers: Vec<(u32, bool)>,
-    pub valid_score: u32,
+    width: usize,
+    lines: Vec<Vec<usize>>,
+    // Indices into `lines` for every cell, and how many cells of each line are marked
+    cell_lines: Vec<Vec<usize>>,
+    line_hits: Vec<usize>,
+    number_index: HashMap<u32, usize>,
+    unmarked_sum: u32,
+    completed_line: Option<usize>,
+    pub valid_score: Option<u32>,
 }
 
 impl fmt::Display for Board {
@@ -15,7 +80,7 @@ impl fmt::Display for Board {
             "{}",
             self.numbers
                 .iter()
-                .chunks(5)
+                .chunks(self.width)
                 .into_iter()
                 .map(|row| {
                     row.map(|(number, marked)| {
@@ -33,134 +98,301 @@ impl fmt::Display for Board {
 }
 
 impl Board {
-    pub fn new(numbers: &str) -> Self {
-        Self {
-            numbers: numbers
-                .split(&['\n', ' '][..])
-                .filter_map(|maybe_number| maybe_number.parse::<u32>().ok().map(|number| (number, false)))
-                .collect::<Vec<(u32, bool)>>(),
-            valid_score: 0,
+    // Fails if the rows don't all have the same width
+    pub fn new(numbers: &str, patterns: &[WinPattern]) -> Result<Self, String> {
+        let rows = numbers
+            .lines()
+            .map(|line| {
+                line.split(' ')
+                    .filter_map(|maybe_number| maybe_number.parse::<u32>().ok())
+                    .collect::<Vec<u32>>()
+            })
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<Vec<u32>>>();
+
+        let width = rows.first().map_or(0, |row| row.len());
+        let height = rows.len();
+        if let Some((row, numbers)) = rows
+            .iter()
+            .enumerate()
+            .find(|(_, numbers)| numbers.len() != width)
+        {
+            return Err(format!(
+                "row {} has {} numbers, expected {}",
+                row,
+                numbers.len(),
+                width
+            ));
+        }
+
+        let numbers = rows
+            .into_iter()
+            .flatten()
+            .map(|number| (number, false))
+            .collect::<Vec<(u32, bool)>>();
+
+        let lines = patterns
+            .iter()
+            .flat_map(|pattern| pattern.lines(width, height))
+            .collect::<Vec<Vec<usize>>>();
+
+        let mut cell_lines = vec![vec![]; numbers.len()];
+        for (line_index, line) in lines.iter().enumerate() {
+            for &index in line {
+                cell_lines[index].push(line_index);
+            }
+        }
+
+        let mut number_index = HashMap::new();
+        for (index, &(number, _)) in numbers.iter().enumerate() {
+            number_index.entry(number).or_insert(index);
         }
+
+        Ok(Self {
+            unmarked_sum: numbers.iter().map(|&(number, _)| number).sum(),
+            line_hits: vec![0; lines.len()],
+            numbers,
+            width,
+            lines,
+            cell_lines,
+            number_index,
+            completed_line: None,
+            valid_score: None,
+        })
     }
 
     pub fn check_drawn_number(&mut self, number: u32) -> Option<u32> {
-        if self.valid_score > 0 {
+        if self.valid_score.is_some() {
             return None;
         }
 
-        match self.numbers.iter().position(|&(value, _)| number == value) {
-            Some(index) => {
-                self.numbers[index] = (number, true);
-                if self.has_winning_criteria() {
-                    self.valid_score = self.score(number);
-                    Some(self.valid_score)
-                } else {
-                    None
-                }
+        let index = *self.number_index.get(&number)?;
+        if self.numbers[index].1 {
+            return None;
+        }
+
+        self.numbers[index].1 = true;
+        self.unmarked_sum -= number;
+
+        for &line_index in &self.cell_lines[index] {
+            self.line_hits[line_index] += 1;
+            if self.completed_line.is_none()
+                && self.line_hits[line_index] == self.lines[line_index].len()
+            {
+                self.completed_line = Some(line_index);
             }
-            None => None,
         }
-    }
 
-    fn has_winning_criteria(&self) -> bool {
-        let row_size = 5;
-        let column_size = 5;
+        if self.has_winning_criteria() {
+            self.valid_score = Some(self.score(number));
+            self.valid_score
+        } else {
+            None
+        }
+    }
 
-        let rows = (0..column_size)
-            .map(|offset| {
-                self.numbers
-                    .iter()
-                    .skip(offset * row_size)
-                    .take(row_size)
-                    .all(|(_, marked)| *marked)
-            })
-            .any(|has_won| has_won);
+    // Fewest cells that are still unmarked on any of the winning lines
+    fn cells_to_win(&self) -> usize {
+        self.lines
+            .iter()
+            .zip(self.line_hits.iter())
+            .map(|(line, hits)| line.len() - hits)
+            .min()
+            .unwrap_or(0)
+    }
 
-        let columns = (0..row_size)
-            .map(|offset| {
-                self.numbers
-                    .iter()
-                    .skip(offset)
-                    .step_by(row_size)
-                    .all(|(_, marked)| *marked)
-            })
-            .any(|has_won| has_won);
+    fn winning_line(&self) -> Option<&Vec<usize>> {
+        self.completed_line
+            .map(|line_index| &self.lines[line_index])
+    }
 
-        rows || columns
+    fn has_winning_criteria(&self) -> bool {
+        self.completed_line.is_some()
     }
 
     fn score(&self, last_drawn_number: u32) -> u32 {
-        last_drawn_number
-            * self.numbers.iter().fold(0, |acc, &(number, marked)| {
-                acc + if !marked { number } else { 0 }
-            })
+        last_drawn_number * self.unmarked_sum
     }
 }
 
-fn solve_part1(inputfile: String) -> usize {
+fn parse_input(inputfile: String, patterns: &[WinPattern]) -> (Vec<u32>, Vec<Board>) {
     let contents =
         std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
 
     let drawn_numbers = contents
-        .lines().next()
+        .lines()
+        .next()
         .unwrap()
         .split(',')
         .map(|number| number.parse::<u32>().unwrap())
         .collect::<Vec<u32>>();
 
-    let mut boards = contents
+    let boards = contents
         .split("\n\n")
         .skip(1)
-        .map(Board::new)
+        .filter(|numbers| !numbers.trim().is_empty())
+        .enumerate()
+        .map(|(board_index, numbers)| {
+            Board::new(numbers, patterns)
+                .unwrap_or_else(|error| panic!("Invalid board {}: {}", board_index, error))
+        })
         .collect::<Vec<Board>>();
 
-    let winning_score = drawn_numbers
-        .iter()
-        .find_map(|&drawn_number| {
-            boards
-                .iter_mut()
-                .filter_map(|board| board.check_drawn_number(drawn_number))
-                .next()
-        })
-        .unwrap();
+    (drawn_numbers, boards)
+}
 
-    winning_score as usize
+#[derive(Debug)]
+struct Win {
+    draw_index: usize,
+    drawn_number: u32,
+    score: u32,
+    line: Vec<u32>,
 }
 
-fn solve_part2(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+#[derive(Debug)]
+struct BoardResult {
+    board_index: usize,
+    win: Option<Win>,
+    // How many more marks the board needed when the game ended, 0 for winners
+    cells_to_win: usize,
+}
 
-    let drawn_numbers = contents
-        .lines().next()
-        .unwrap()
-        .split(',')
-        .map(|number| number.parse::<u32>().unwrap())
-        .collect::<Vec<u32>>();
+fn simulate(drawn_numbers: &[u32], boards: &mut [Board], dump_boards: bool) -> Vec<BoardResult> {
+    let mut wins = boards.iter().map(|_| None).collect::<Vec<Option<Win>>>();
 
-    let mut boards = contents
-        .split("\n\n")
-        .skip(1)
-        .map(Board::new)
-        .collect::<Vec<Board>>();
+    // Only the boards that contain a drawn number need to be checked
+    let mut boards_by_number = HashMap::<u32, Vec<usize>>::new();
+    for (board_index, board) in boards.iter().enumerate() {
+        for &number in board.number_index.keys() {
+            boards_by_number
+                .entry(number)
+                .or_default()
+                .push(board_index);
+        }
+    }
 
-    let winning_scores = drawn_numbers
-        .iter()
-        .filter_map(|&drawn_number| {
-            let boards_won = boards
-                .iter_mut()
-                .map(|board| board.check_drawn_number(drawn_number))
-                .collect::<Vec<Option<u32>>>();
+    for (draw_index, &drawn_number) in drawn_numbers.iter().enumerate() {
+        let affected_boards = match boards_by_number.get(&drawn_number) {
+            Some(affected_boards) => affected_boards,
+            None => continue,
+        };
 
-            boards_won.iter().filter_map(|&score| score).next()
+        for &board_index in affected_boards {
+            let board = &mut boards[board_index];
+            if let Some(score) = board.check_drawn_number(drawn_number) {
+                let line = board
+                    .winning_line()
+                    .unwrap()
+                    .iter()
+                    .map(|&index| board.numbers[index].0)
+                    .collect::<Vec<u32>>();
+
+                if dump_boards {
+                    println!(
+                        "Board {} wins on draw {} ({}), score {}:\n{}\n",
+                        board_index, draw_index, drawn_number, score, board
+                    );
+                }
+
+                wins[board_index] = Some(Win {
+                    draw_index,
+                    drawn_number,
+                    score,
+                    line,
+                });
+            }
+        }
+    }
+
+    wins.into_iter()
+        .zip(boards.iter())
+        .enumerate()
+        .map(|(board_index, (win, board))| BoardResult {
+            board_index,
+            win,
+            cells_to_win: board.cells_to_win(),
         })
-        .collect::<Vec<u32>>();
+        .collect()
+}
+
+// Winners in the order they won, followed by the boards that never won,
+// closest to winning first
+fn leaderboard(results: &[BoardResult]) -> Vec<&BoardResult> {
+    results
+        .iter()
+        .sorted_by_key(|result| {
+            (
+                result.win.as_ref().map_or(usize::MAX, |win| win.draw_index),
+                result.cells_to_win,
+                result.board_index,
+            )
+        })
+        .collect()
+}
+
+fn solve_part1(results: &[BoardResult]) -> Option<usize> {
+    let first_winner = leaderboard(results)[0];
+    first_winner.win.as_ref().map(|win| win.score as usize)
+}
+
+fn solve_part2(results: &[BoardResult]) -> Option<usize> {
+    // Boards winning on the same draw are ranked by their order in the input
+    let last_winner = results
+        .iter()
+        .rev()
+        .filter_map(|result| result.win.as_ref())
+        .max_by_key(|win| win.draw_index)?;
+    Some(last_winner.score as usize)
+}
 
-    *winning_scores.last().unwrap() as usize
+fn print_timeline(results: &[BoardResult]) {
+    for (rank, result) in leaderboard(results).iter().enumerate() {
+        match &result.win {
+            Some(win) => println!(
+                "{:>4}. board {:>4}: wins on draw {} ({}), score {}, line {:?}",
+                rank + 1,
+                result.board_index,
+                win.draw_index,
+                win.drawn_number,
+                win.score,
+                win.line
+            ),
+            None => println!(
+                "{:>4}. board {:>4}: never wins, {} cells from winning",
+                rank + 1,
+                result.board_index,
+                result.cells_to_win
+            ),
+        }
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    println!("Part1: {}", solve_part1(args[1].to_string()));
-    println!("Part2: {}", solve_part2(args[1].to_string()));
+
+    // Win patterns are given as a comma separated list, e.g. "rows,columns,diagonals"
+    let patterns = match args.iter().skip(2).find(|arg| !arg.starts_with("--")) {
+        Some(names) => names
+            .split(',')
+            .map(|name| WinPattern::parse(name).expect("Unknown win pattern"))
+            .collect::<Vec<WinPattern>>(),
+        None => vec![WinPattern::Rows, WinPattern::Columns],
+    };
+
+    let dump_boards = args.iter().any(|arg| arg == "--dump");
+    let (drawn_numbers, mut boards) = parse_input(args[1].to_string(), &patterns);
+    let results = simulate(&drawn_numbers, &mut boards, dump_boards);
+
+    match solve_part1(&results) {
+        Some(score) => println!("Part1: {}", score),
+        None => println!("Part1: no board wins"),
+    }
+    match solve_part2(&results) {
+        Some(score) => println!("Part2: {}", score),
+        None => println!("Part2: no board wins"),
+    }
+
+    if args.iter().any(|arg| arg == "--timeline") {
+        print_timeline(&results);
+    }
 }
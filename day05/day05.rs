@@ -1,21 +1,57 @@
 use image::ImageBuffer;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 
 type Coordinate = (i32, i32);
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineMode {
+    // Horizontal, vertical and 45 degree lines only, other lines are skipped
+    Diagonal,
+    // Every line, approximated by the closest grid points
+    Bresenham,
+    // Every line, but only the grid points that are exactly on it
+    Lattice,
+}
+
+impl LineMode {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "diagonal" => Some(LineMode::Diagonal),
+            "bresenham" => Some(LineMode::Bresenham),
+            "lattice" => Some(LineMode::Lattice),
+            _ => None,
+        }
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Steps between the lattice points of a line, which for horizontal, vertical
+// and 45 degree lines is one grid point at a time
 struct CoordinateRange {
     start: Coordinate,
     current: Option<Coordinate>,
     end: Coordinate,
+    step: Coordinate,
 }
 impl CoordinateRange {
     fn new(start_pos: Coordinate, end_pos: Coordinate) -> CoordinateRange {
+        let delta = (end_pos.0 - start_pos.0, end_pos.1 - start_pos.1);
+        let divisor = gcd(delta.0, delta.1).max(1);
+
         CoordinateRange {
             start: start_pos,
             current: None,
             end: end_pos,
+            step: (delta.0 / divisor, delta.1 / divisor),
         }
     }
 }
@@ -33,25 +69,448 @@ impl Iterator for CoordinateRange {
                     return None;
                 }
 
-                if current.0 != self.end.0 {
-                    if current.0 > self.end.0 {
-                        current.0 -= 1;
-                    } else {
-                        current.0 += 1;
+                current.0 += self.step.0;
+                current.1 += self.step.1;
+
+                self.current
+            }
+        }
+    }
+}
+
+struct BresenhamRange {
+    current: Option<Coordinate>,
+    end: Coordinate,
+    delta: Coordinate,
+    step: Coordinate,
+    error: i32,
+}
+impl BresenhamRange {
+    fn new(start_pos: Coordinate, end_pos: Coordinate) -> BresenhamRange {
+        let delta = (
+            (end_pos.0 - start_pos.0).abs(),
+            -(end_pos.1 - start_pos.1).abs(),
+        );
+
+        BresenhamRange {
+            current: Some(start_pos),
+            end: end_pos,
+            delta,
+            step: (
+                (end_pos.0 - start_pos.0).signum(),
+                (end_pos.1 - start_pos.1).signum(),
+            ),
+            error: delta.0 + delta.1,
+        }
+    }
+}
+
+impl Iterator for BresenhamRange {
+    type Item = Coordinate;
+    fn next(&mut self) -> Option<Coordinate> {
+        let current = self.current?;
+
+        if current == self.end {
+            self.current = None;
+            return Some(current);
+        }
+
+        let mut next = current;
+        let doubled_error = 2 * self.error;
+        if doubled_error >= self.delta.1 {
+            self.error += self.delta.1;
+            next.0 += self.step.0;
+        }
+        if doubled_error <= self.delta.0 {
+            self.error += self.delta.0;
+            next.1 += self.step.1;
+        }
+        self.current = Some(next);
+
+        Some(current)
+    }
+}
+
+fn is_diagonal_or_straight(start: Coordinate, stop: Coordinate) -> bool {
+    (start.0 == stop.0)
+        || (start.1 == stop.1)
+        || ((start.0 - stop.0).abs() == (start.1 - stop.1).abs())
+}
+
+fn rasterise(start: Coordinate, stop: Coordinate, mode: LineMode) -> Option<Vec<Coordinate>> {
+    match mode {
+        LineMode::Diagonal => {
+            if is_diagonal_or_straight(start, stop) {
+                Some(CoordinateRange::new(start, stop).collect())
+            } else {
+                None
+            }
+        }
+        LineMode::Bresenham => Some(BresenhamRange::new(start, stop).collect()),
+        LineMode::Lattice => Some(CoordinateRange::new(start, stop).collect()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Direction {
+    Horizontal,
+    Vertical,
+    Diagonal,
+    AntiDiagonal,
+}
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Horizontal,
+    Direction::Vertical,
+    Direction::Diagonal,
+    Direction::AntiDiagonal,
+];
+
+// Every line in a direction is identified by a key, and the points on it by
+// their position along the line, one grid point per step
+impl Direction {
+    fn of(start: Coordinate, stop: Coordinate) -> Option<Self> {
+        let delta = (stop.0 - start.0, stop.1 - start.1);
+        if delta.1 == 0 {
+            Some(Direction::Horizontal)
+        } else if delta.0 == 0 {
+            Some(Direction::Vertical)
+        } else if delta.0 == delta.1 {
+            Some(Direction::Diagonal)
+        } else if delta.0 == -delta.1 {
+            Some(Direction::AntiDiagonal)
+        } else {
+            None
+        }
+    }
+
+    // The line is described by a * x + b * y = key
+    fn normal(&self) -> Coordinate {
+        match self {
+            Direction::Horizontal => (0, 1),
+            Direction::Vertical => (1, 0),
+            Direction::Diagonal => (1, -1),
+            Direction::AntiDiagonal => (1, 1),
+        }
+    }
+
+    fn key(&self, point: Coordinate) -> i32 {
+        let (a, b) = self.normal();
+        a * point.0 + b * point.1
+    }
+
+    fn position(&self, point: Coordinate) -> i32 {
+        match self {
+            Direction::Vertical => point.1,
+            _ => point.0,
+        }
+    }
+
+    fn point(&self, key: i32, position: i32) -> Coordinate {
+        match self {
+            Direction::Horizontal => (position, key),
+            Direction::Vertical => (key, position),
+            Direction::Diagonal => (position, position - key),
+            Direction::AntiDiagonal => (position, key - position),
+        }
+    }
+
+    fn intersection(&self, key: i32, other: Direction, other_key: i32) -> Option<Coordinate> {
+        let (a, b) = self.normal();
+        let (other_a, other_b) = other.normal();
+        let determinant = a * other_b - other_a * b;
+        if determinant == 0 {
+            return None;
+        }
+
+        let x = key * other_b - other_key * b;
+        let y = a * other_key - other_a * key;
+        if x % determinant != 0 || y % determinant != 0 {
+            return None;
+        }
+        Some((x / determinant, y / determinant))
+    }
+
+    // The keys of the lines passing through a rectangle
+    fn key_range(&self, min: Coordinate, max: Coordinate) -> (i32, i32) {
+        match self {
+            Direction::Horizontal => (min.1, max.1),
+            Direction::Vertical => (min.0, max.0),
+            Direction::Diagonal => (min.0 - max.1, max.0 - min.1),
+            Direction::AntiDiagonal => (min.0 + min.1, max.0 + max.1),
+        }
+    }
+
+    // The positions along a line that are inside a rectangle
+    fn position_range(&self, key: i32, min: Coordinate, max: Coordinate) -> (i32, i32) {
+        match self {
+            Direction::Horizontal => (min.0, max.0),
+            Direction::Vertical => (min.1, max.1),
+            Direction::Diagonal => (min.0.max(min.1 + key), max.0.min(max.1 + key)),
+            Direction::AntiDiagonal => (min.0.max(key - max.1), max.0.min(key - min.1)),
+        }
+    }
+}
+
+// A stretch of a line as (first position, last position, number of segments)
+type Piece = (i32, i32, usize);
+
+fn sweep(mut segments: Vec<(i32, i32)>) -> Vec<Piece> {
+    segments.sort_unstable();
+
+    let mut events = segments
+        .iter()
+        .flat_map(|&(first, last)| [(first, 1), (last + 1, -1)])
+        .collect::<Vec<(i32, i32)>>();
+    events.sort_unstable();
+
+    let mut pieces = vec![];
+    let mut count = 0;
+    for (&(position, change), next) in events.iter().zip(events.iter().skip(1)) {
+        count += change;
+        if count > 0 && next.0 > position {
+            pieces.push((position, next.0 - 1, count as usize));
+        }
+    }
+    pieces
+}
+
+fn coverage_at(pieces: &[Piece], position: i32) -> usize {
+    let index = pieces.partition_point(|piece| piece.1 < position);
+    match pieces.get(index) {
+        Some(&(first, _, count)) if first <= position => count,
+        _ => 0,
+    }
+}
+
+// Joins the pieces that are directly next to each other
+fn covered_ranges(pieces: &[Piece]) -> Vec<(i32, i32)> {
+    let mut ranges: Vec<(i32, i32)> = vec![];
+    for &(first, last, _) in pieces {
+        match ranges.last_mut() {
+            Some(range) if range.1 + 1 == first => range.1 = last,
+            _ => ranges.push((first, last)),
+        }
+    }
+    ranges
+}
+
+struct OverlapCounter {
+    lines: HashMap<Direction, BTreeMap<i32, Vec<Piece>>>,
+    // Points covered by lines in more than one direction
+    crossings: HashSet<Coordinate>,
+    // Rasterised points of the lines that aren't straight or diagonal
+    scattered: HashMap<Coordinate, usize>,
+}
+
+impl OverlapCounter {
+    fn new(input: &[(Coordinate, Coordinate)], mode: LineMode) -> Self {
+        let mut segments = HashMap::<Direction, BTreeMap<i32, Vec<(i32, i32)>>>::new();
+        let mut scattered = HashMap::new();
+
+        for &(start, stop) in input {
+            match Direction::of(start, stop) {
+                Some(direction) => {
+                    let (first, last) = (direction.position(start), direction.position(stop));
+                    segments
+                        .entry(direction)
+                        .or_default()
+                        .entry(direction.key(start))
+                        .or_default()
+                        .push((first.min(last), first.max(last)));
+                }
+                None => match rasterise(start, stop, mode) {
+                    Some(coords) => {
+                        for coord in coords {
+                            *scattered.entry(coord).or_insert(0) += 1;
+                        }
+                    }
+                    None => {
+                        println!(
+                            " strange line {:?}, {:?}",
+                            (start.0 - stop.0).abs(),
+                            (start.1 - stop.1).abs()
+                        );
+                    }
+                },
+            }
+        }
+
+        let lines = DIRECTIONS
+            .iter()
+            .map(|&direction| {
+                let pieces = segments
+                    .remove(&direction)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(key, segments)| (key, sweep(segments)))
+                    .collect::<BTreeMap<i32, Vec<Piece>>>();
+                (direction, pieces)
+            })
+            .collect::<HashMap<Direction, BTreeMap<i32, Vec<Piece>>>>();
+
+        let mut crossings = HashSet::new();
+        for (index, direction) in DIRECTIONS.iter().enumerate() {
+            for other in DIRECTIONS.iter().skip(index + 1) {
+                for (&key, pieces) in &lines[direction] {
+                    for (first, last) in covered_ranges(pieces) {
+                        let first_key = other.key(direction.point(key, first));
+                        let last_key = other.key(direction.point(key, last));
+
+                        for (&other_key, other_pieces) in
+                            lines[other].range(first_key.min(last_key)..=first_key.max(last_key))
+                        {
+                            if let Some(point) = direction.intersection(key, *other, other_key) {
+                                if coverage_at(other_pieces, other.position(point)) > 0 {
+                                    crossings.insert(point);
+                                }
+                            }
+                        }
                     }
                 }
+            }
+        }
+
+        Self {
+            lines,
+            crossings,
+            scattered,
+        }
+    }
+
+    fn line_coverage(&self, direction: Direction, point: Coordinate) -> usize {
+        match self.lines[&direction].get(&direction.key(point)) {
+            Some(pieces) => coverage_at(pieces, direction.position(point)),
+            None => 0,
+        }
+    }
+
+    fn straight_coverage(&self, point: Coordinate) -> usize {
+        DIRECTIONS
+            .iter()
+            .map(|&direction| self.line_coverage(direction, point))
+            .sum()
+    }
+
+    // Number of lines covering a point
+    fn coverage(&self, point: Coordinate) -> usize {
+        self.straight_coverage(point) + self.scattered.get(&point).unwrap_or(&0)
+    }
+
+    fn is_scattered_overlap(&self, point: Coordinate, count: usize) -> bool {
+        let straight_coverage = self.straight_coverage(point);
+        straight_coverage < 2 && straight_coverage + count > 1
+    }
+
+    fn count(&self) -> usize {
+        let collinear = self
+            .lines
+            .values()
+            .flat_map(|pieces| pieces.values().flatten())
+            .filter(|piece| piece.2 > 1)
+            .map(|(first, last, _)| (last - first + 1) as usize)
+            .sum::<usize>();
+
+        // A crossing is already counted once for every direction it overlaps in
+        let crossing = self
+            .crossings
+            .iter()
+            .map(|&point| {
+                let overlapping_directions = DIRECTIONS
+                    .iter()
+                    .filter(|&&direction| self.line_coverage(direction, point) > 1)
+                    .count();
+                1 - overlapping_directions as i64
+            })
+            .sum::<i64>();
+
+        let scattered = self
+            .scattered
+            .iter()
+            .filter(|(&point, &count)| self.is_scattered_overlap(point, count))
+            .count();
+
+        (collinear as i64 + crossing) as usize + scattered
+    }
 
-                if current.1 != self.end.1 {
-                    if current.1 > self.end.1 {
-                        current.1 -= 1;
-                    } else {
-                        current.1 += 1;
+    // Highest coverage within every block of cell_size x cell_size points, keyed by
+    // the block. Pieces are walked a block at a time, so this stays proportional to
+    // the number of blocks touched rather than the length of the lines.
+    fn block_coverage(&self, cell_size: i32) -> HashMap<Coordinate, usize> {
+        let block =
+            |point: Coordinate| (point.0.div_euclid(cell_size), point.1.div_euclid(cell_size));
+        // Positions until a coordinate moving by step leaves its block
+        let steps_left = |value: i32, step: i32| match step {
+            1 => cell_size - value.rem_euclid(cell_size),
+            -1 => value.rem_euclid(cell_size) + 1,
+            _ => i32::MAX,
+        };
+
+        let mut blocks = HashMap::<Coordinate, usize>::new();
+        let mut add = |block: Coordinate, count: usize| {
+            let max_count = blocks.entry(block).or_insert(0);
+            *max_count = (*max_count).max(count);
+        };
+
+        for (direction, lines) in &self.lines {
+            for (&key, pieces) in lines {
+                for &(first, last, count) in pieces {
+                    let mut position = first;
+                    while position <= last {
+                        let point = direction.point(key, position);
+                        let next = direction.point(key, position + 1);
+                        let step = (next.0 - point.0, next.1 - point.1);
+
+                        add(block(point), count);
+                        position += steps_left(point.0, step.0).min(steps_left(point.1, step.1));
                     }
                 }
+            }
+        }
 
-                self.current
+        // Points where lines in different directions overlap
+        for &point in self.crossings.iter().chain(self.scattered.keys()) {
+            add(block(point), self.coverage(point));
+        }
+        blocks
+    }
+
+    // All points covered by more than one line inside the rectangle from min to max
+    fn overlap_points(&self, min: Coordinate, max: Coordinate) -> Vec<Coordinate> {
+        let is_inside = |point: &Coordinate| {
+            point.0 >= min.0 && point.0 <= max.0 && point.1 >= min.1 && point.1 <= max.1
+        };
+
+        let mut points = BTreeSet::new();
+
+        for direction in DIRECTIONS {
+            let (first_key, last_key) = direction.key_range(min, max);
+            if first_key > last_key {
+                continue;
+            }
+
+            for (&key, pieces) in self.lines[&direction].range(first_key..=last_key) {
+                let (first_inside, last_inside) = direction.position_range(key, min, max);
+
+                for &(first, last, _) in pieces.iter().filter(|piece| piece.2 > 1) {
+                    for position in first.max(first_inside)..=last.min(last_inside) {
+                        points.insert(direction.point(key, position));
+                    }
+                }
             }
         }
+
+        points.extend(self.crossings.iter().filter(|point| is_inside(point)));
+        points.extend(
+            self.scattered
+                .iter()
+                .filter(|(point, &count)| {
+                    is_inside(point) && self.is_scattered_overlap(**point, count)
+                })
+                .map(|(&point, _)| point),
+        );
+
+        points.into_iter().collect()
     }
 }
 
@@ -81,140 +540,361 @@ fn parse_input(inputfile: String) -> Vec<(Coordinate, Coordinate)> {
 }
 
 fn solve_part1(inputfile: String) -> usize {
+    let input = parse_input(inputfile)
+        .into_iter()
+        .filter(|(start, stop)| (start.0 == stop.0) || (start.1 == stop.1))
+        .collect::<Vec<(Coordinate, Coordinate)>>();
+
+    OverlapCounter::new(&input, LineMode::Diagonal).count()
+}
+
+fn solve_part2(inputfile: String, mode: LineMode) -> usize {
     let input = parse_input(inputfile);
 
-    let mut vent_map = HashMap::<Coordinate, i32>::new();
+    OverlapCounter::new(&input, mode).count()
+}
 
-    for (start, stop) in input {
-        if (start.0 == stop.0) || (start.1 == stop.1) {
-            for coord in CoordinateRange::new(start, stop) {
-                vent_map
-                    .entry(coord)
-                    .and_modify(|e| *e += 1)
-                    .or_insert(1);
-            }
-        }
-    }
+type Color = (u8, u8, u8);
 
-    vent_map
-        .iter()
-        .fold(0, |acc, (_, &value)| if value > 1 { acc + 1 } else { acc })
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IntensityScale {
+    Linear,
+    Logarithmic,
 }
 
-fn solve_part2(inputfile: String) -> usize {
-    let input = parse_input(inputfile);
+impl IntensityScale {
+    // Maps an overlap count from 1 to max_count onto 0.0 to 1.0
+    fn intensity(&self, count: usize, max_count: usize) -> f64 {
+        if max_count <= 1 {
+            return 0.0;
+        }
 
-    let mut vent_map = HashMap::<Coordinate, i32>::new();
-
-    for (start, stop) in input {
-        if (start.0 == stop.0)
-            || (start.1 == stop.1)
-            || ((start.0 - stop.0).abs() == (start.1 - stop.1).abs())
-        {
-            for coord in CoordinateRange::new(start, stop) {
-                vent_map
-                    .entry(coord)
-                    .and_modify(|e| *e += 1)
-                    .or_insert(1);
-            }
-        } else {
-            println!(
-                " strange line {:?}, {:?}",
-                (start.0 - stop.0).abs(),
-                (start.1 - stop.1).abs()
-            );
+        match self {
+            IntensityScale::Linear => (count - 1) as f64 / (max_count - 1) as f64,
+            IntensityScale::Logarithmic => (count as f64).ln() / (max_count as f64).ln(),
         }
     }
 
-    vent_map
-        .iter()
-        .fold(0, |acc, (_, &value)| if value > 1 { acc + 1 } else { acc })
+    fn count(&self, intensity: f64, max_count: usize) -> usize {
+        match self {
+            IntensityScale::Linear => 1 + (intensity * (max_count - 1) as f64).round() as usize,
+            IntensityScale::Logarithmic => (max_count as f64).powf(intensity).round() as usize,
+        }
+    }
 }
 
-type Color = (u8, u8, u8);
+const HEAT_PALETTE: [Color; 5] = [
+    (219, 209, 180),
+    (194, 178, 128),
+    (166, 145, 80),
+    (140, 90, 40),
+    (100, 40, 20),
+];
 
-fn draw_pixel(pixels: &mut Vec<(Coordinate, Color)>, position: Coordinate, color_index: i32) {
-    let color = match color_index {
-        1 => (166, 145, 80),
-        2 => (177, 157, 94),
-        3 => (186, 168, 111),
-        4 => (194, 178, 128),
-        5 => (202, 188, 145),
-        6 => (211, 199, 162),
-        7 => (219, 209, 180),
-        _ => (219, 209, 180),
-    };
+fn heat_color(intensity: f64) -> Color {
+    let position = intensity.clamp(0.0, 1.0) * (HEAT_PALETTE.len() - 1) as f64;
+    let index = (position.floor() as usize).min(HEAT_PALETTE.len() - 2);
+    let fraction = position - index as f64;
+
+    let (from, to) = (HEAT_PALETTE[index], HEAT_PALETTE[index + 1]);
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * fraction).round() as u8;
 
-    pixels.push((position, color));
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
 }
 
-fn draw_vent_map(inputfile: String) {
-    let input = parse_input(inputfile);
+// 3x5 pixel glyphs for the legend labels, one row per byte
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+fn draw_number(pixels: &mut Vec<(Coordinate, Color)>, position: Coordinate, number: usize) {
+    for (index, digit) in number.to_string().chars().enumerate() {
+        let glyph = DIGITS[digit.to_digit(10).unwrap() as usize];
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    pixels.push((
+                        (
+                            position.0 + 4 * index as i32 + column,
+                            position.1 + row as i32,
+                        ),
+                        (0, 0, 0),
+                    ));
+                }
+            }
+        }
+    }
+}
 
-    let mut vent_map = HashMap::<Coordinate, i32>::new();
-
-    for (start, stop) in input {
-        if (start.0 == stop.0)
-            || (start.1 == stop.1)
-            || ((start.0 - stop.0).abs() == (start.1 - stop.1).abs())
-        {
-            for coord in CoordinateRange::new(start, stop) {
-                vent_map
-                    .entry(coord)
-                    .and_modify(|e| *e += 1)
-                    .or_insert(1);
+// Draws a swatch for a few overlap counts spread over the scale, with the count below it.
+// Returns the size of the legend.
+fn draw_legend(
+    pixels: &mut Vec<(Coordinate, Color)>,
+    position: Coordinate,
+    max_count: usize,
+    scale: IntensityScale,
+) -> Coordinate {
+    let num_levels = max_count.clamp(1, 8);
+    let levels = (0..num_levels)
+        .map(|level| {
+            let intensity = if num_levels > 1 {
+                level as f64 / (num_levels - 1) as f64
+            } else {
+                0.0
+            };
+            scale.count(intensity, max_count).max(1)
+        })
+        .dedup()
+        .collect::<Vec<usize>>();
+
+    let swatch_size = 5;
+    let mut x = position.0;
+    for count in levels {
+        let color = heat_color(scale.intensity(count, max_count));
+        for offset_y in 0..swatch_size {
+            for offset_x in 0..swatch_size {
+                pixels.push(((x + offset_x, position.1 + offset_y), color));
             }
         }
+        draw_number(pixels, (x, position.1 + swatch_size + 1), count);
+
+        let label_width = 4 * count.to_string().len() as i32;
+        x += swatch_size.max(label_width) + 2;
     }
 
-    let x_min = vent_map.iter().map(|(pos, _)| pos.0).min().unwrap();
-    let x_max = vent_map.iter().map(|(pos, _)| pos.0).max().unwrap();
-    let y_min = vent_map.iter().map(|(pos, _)| pos.1).min().unwrap();
-    let y_max = vent_map.iter().map(|(pos, _)| pos.1).max().unwrap();
-    let x_range = (x_max - x_min) as u32;
-    let y_range = (y_max - y_min) as u32;
-    let dimensions: Coordinate = (1 + x_range as i32, 1 + y_range as i32);
+    (x - position.0, 2 * swatch_size + 1)
+}
 
-    let border = 2;
-    let real_size = (
-        ((dimensions.0 + border * 2) as u32),
-        ((dimensions.1 + border * 2) as u32),
+// Largest image drawn, in points of the vent map. Bigger maps are drawn with every
+// pixel covering a block of points.
+const MAX_MAP_PIXELS: i64 = 1 << 22;
+
+fn draw_vent_map(inputfile: String, mode: LineMode, scale: IntensityScale, upscale: u32) {
+    let input = parse_input(inputfile);
+    if input.is_empty() {
+        return;
+    }
+
+    let vent_map = OverlapCounter::new(&input, mode);
+
+    let endpoints = input
+        .iter()
+        .flat_map(|&(start, stop)| [start, stop])
+        .collect::<Vec<Coordinate>>();
+    let x_min = endpoints.iter().map(|pos| pos.0).min().unwrap();
+    let x_max = endpoints.iter().map(|pos| pos.0).max().unwrap();
+    let y_min = endpoints.iter().map(|pos| pos.1).min().unwrap();
+    let y_max = endpoints.iter().map(|pos| pos.1).max().unwrap();
+    let extent = (1 + (x_max - x_min) as i64, 1 + (y_max - y_min) as i64);
+
+    let mut cell_size = 1;
+    while (extent.0 + cell_size - 1) / cell_size * ((extent.1 + cell_size - 1) / cell_size)
+        > MAX_MAP_PIXELS
+    {
+        cell_size *= 2;
+    }
+    if cell_size > 1 {
+        println!(
+            "Vent map is {}x{}, drawing every {}x{} block as one pixel",
+            extent.0, extent.1, cell_size, cell_size
+        );
+    }
+    let cell_size = cell_size as i32;
+
+    let origin = (x_min.div_euclid(cell_size), y_min.div_euclid(cell_size));
+    let dimensions: Coordinate = (
+        1 + x_max.div_euclid(cell_size) - origin.0,
+        1 + y_max.div_euclid(cell_size) - origin.1,
     );
 
-    let offset = (x_min + border, y_min + border);
+    let coverage = vent_map.block_coverage(cell_size);
+    let max_count = coverage.values().copied().max().unwrap_or(1);
 
+    let border = 2;
     let mut pixels = Vec::<(Coordinate, Color)>::new();
 
-    for y in 0..y_range {
-        for x in 0..x_range {
-            let block_pos = (border + x as i32, border + y as i32);
-            let vent_pos = (offset.0 + block_pos.0, offset.1 + block_pos.1);
-
-            match vent_map.get(&vent_pos) {
-                Some(&height) => draw_pixel(&mut pixels, block_pos, height),
-                None => draw_pixel(&mut pixels, block_pos, 0),
-            }
-        }
+    for (block, count) in coverage {
+        let block_pos = (border + block.0 - origin.0, border + block.1 - origin.1);
+        pixels.push((block_pos, heat_color(scale.intensity(count, max_count))));
     }
 
-    let mut img = ImageBuffer::from_fn(real_size.0, real_size.1, |_x, _y| {
+    let legend_size = draw_legend(
+        &mut pixels,
+        (border, 2 * border + dimensions.1),
+        max_count,
+        scale,
+    );
+
+    let real_size = (
+        (dimensions.0.max(legend_size.0) + border * 2) as u32,
+        (dimensions.1 + legend_size.1 + border * 3) as u32,
+    );
+
+    let mut img = ImageBuffer::from_fn(upscale * real_size.0, upscale * real_size.1, |_x, _y| {
         image::Rgb([255, 255, 255])
     });
 
     for ((x, y), color) in pixels {
         let pixel = image::Rgb([color.0, color.1, color.2]);
         if x >= 0 && y >= 0 && x < real_size.0 as i32 && y < real_size.1 as i32 {
-            img.put_pixel(x as u32, y as u32, pixel);
+            for offset_y in 0..upscale {
+                for offset_x in 0..upscale {
+                    img.put_pixel(
+                        upscale * x as u32 + offset_x,
+                        upscale * y as u32 + offset_y,
+                        pixel,
+                    );
+                }
+            }
         }
     }
 
-    img.save("frames/day05.png".to_string()).unwrap();
+    img.save("frames/day05.png").unwrap();
+}
+
+fn parse_coordinates(text: &str) -> Vec<i32> {
+    text.split(',')
+        .map(|value| value.parse::<i32>().expect("Invalid coordinate"))
+        .collect()
+}
+
+// Answers "--point=x,y" and "--region=x0,y0,x1,y1" queries against the vent map,
+// other options are ignored
+fn query_vent_map(inputfile: String, mode: LineMode, queries: &[&String]) {
+    let input = parse_input(inputfile);
+    let vent_map = OverlapCounter::new(&input, mode);
+
+    for query in queries {
+        if let Some(point) = query.strip_prefix("--point=") {
+            let point = parse_coordinates(point);
+            println!(
+                "lines covering {:?}: {}",
+                (point[0], point[1]),
+                vent_map.coverage((point[0], point[1]))
+            );
+        } else if let Some(region) = query.strip_prefix("--region=") {
+            let region = parse_coordinates(region);
+            let (min, max) = ((region[0], region[1]), (region[2], region[3]));
+            println!(
+                "overlaps in {:?} - {:?}: {:?}",
+                min,
+                max,
+                vent_map.overlap_points(min, max)
+            );
+        }
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let (queries, options): (Vec<&String>, Vec<&String>) =
+        args.iter().skip(2).partition(|arg| arg.starts_with("--"));
+
+    let mode = match options.first() {
+        Some(name) => LineMode::parse(name).expect("Unknown line mode"),
+        None => LineMode::Diagonal,
+    };
+
     println!("Part1: {}", solve_part1(args[1].to_string()));
-    println!("Part2: {}", solve_part2(args[1].to_string()));
+    println!("Part2: {}", solve_part2(args[1].to_string(), mode));
+
+    if !queries.is_empty() {
+        query_vent_map(args[1].to_string(), mode, &queries);
+    }
+
+    let scale = if queries.iter().any(|arg| *arg == "--log") {
+        IntensityScale::Logarithmic
+    } else {
+        IntensityScale::Linear
+    };
+    let upscale = queries
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--upscale="))
+        .map_or(1, |factor| {
+            factor.parse::<u32>().expect("Invalid upscale factor")
+        });
+
+    // e.g. "--draw --log --upscale=4"
+    if queries.iter().any(|arg| *arg == "--draw") {
+        draw_vent_map(args[1].to_string(), mode, scale, upscale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brute_force(
+        input: &[(Coordinate, Coordinate)],
+        mode: LineMode,
+    ) -> HashMap<Coordinate, usize> {
+        let mut vent_map = HashMap::new();
+        for &(start, stop) in input {
+            for coord in rasterise(start, stop, mode).unwrap_or_default() {
+                *vent_map.entry(coord).or_insert(0) += 1;
+            }
+        }
+        vent_map
+    }
+
+    fn generate_lines(count: usize) -> Vec<(Coordinate, Coordinate)> {
+        let mut seed: u64 = 12345;
+        let mut next = move |modulo: i32| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((seed >> 33) % modulo as u64) as i32 - modulo / 2
+        };
+
+        (0..count)
+            .map(|_| {
+                let start = (next(30), next(30));
+                let length = next(20);
+                let stop = match next(8) + 4 {
+                    0 => (start.0 + length, start.1),
+                    1 => (start.0, start.1 + length),
+                    2 | 3 => (start.0 + length, start.1 + length),
+                    4 | 5 => (start.0 + length, start.1 - length),
+                    _ => (next(30), next(30)),
+                };
+                (start, stop)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_overlap_count_matches_rasterised_map() {
+        let input = generate_lines(200);
+
+        for mode in [LineMode::Diagonal, LineMode::Bresenham, LineMode::Lattice] {
+            let vent_map = brute_force(&input, mode);
+            let counter = OverlapCounter::new(&input, mode);
 
-    draw_vent_map(args[1].to_string());
+            let expected = vent_map.values().filter(|&&count| count > 1).count();
+            assert_eq!(counter.count(), expected);
+
+            for (&point, &count) in &vent_map {
+                assert_eq!(counter.coverage(point), count);
+            }
+
+            let expected_points = vent_map
+                .iter()
+                .filter(|(point, &count)| {
+                    count > 1 && point.0 >= -5 && point.0 <= 10 && point.1 >= -8 && point.1 <= 3
+                })
+                .map(|(&point, _)| point)
+                .sorted()
+                .collect::<Vec<Coordinate>>();
+            assert_eq!(counter.overlap_points((-5, -8), (10, 3)), expected_points);
+        }
+    }
 }
@@ -1,87 +1,295 @@
-use itertools::Itertools;
-use std::collections::HashMap;
+use num_bigint::BigUint;
 use std::env;
 
-fn solve_part1(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+type Matrix = Vec<Vec<BigUint>>;
 
-    let mut fishes = contents
-        .lines()
-        .next()
-        .unwrap()
-        .split(',')
-        .map(|cycletime| cycletime.parse::<usize>().unwrap())
-        .collect::<Vec<usize>>();
+#[derive(Debug, Clone, PartialEq)]
+enum Precision {
+    Exact,
+    Modulo(BigUint),
+}
+
+impl Precision {
+    fn reduce(&self, value: BigUint) -> BigUint {
+        match self {
+            Precision::Exact => value,
+            Precision::Modulo(modulus) => value % modulus,
+        }
+    }
+}
 
-    for _ in 0..80 {
-        let new_fishes = fishes
-            .iter_mut()
-            .filter_map(|cycle_time| {
-                if *cycle_time == 0 {
-                    *cycle_time = 6;
-                    Some(8)
-                } else {
-                    *cycle_time -= 1;
-                    None
+fn identity(size: usize) -> Matrix {
+    (0..size)
+        .map(|row| {
+            (0..size)
+                .map(|column| BigUint::from((row == column) as u32))
+                .collect()
+        })
+        .collect()
+}
+
+fn multiply(a: &Matrix, b: &Matrix, precision: &Precision) -> Matrix {
+    (0..a.len())
+        .map(|row| {
+            (0..b[0].len())
+                .map(|column| {
+                    precision.reduce(
+                        (0..b.len())
+                            .map(|index| &a[row][index] * &b[index][column])
+                            .sum(),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn matrix_power(matrix: &Matrix, mut exponent: u64, precision: &Precision) -> Matrix {
+    let mut result = identity(matrix.len());
+    let mut base = matrix.clone();
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply(&result, &base, precision);
+        }
+        base = multiply(&base, &base, precision);
+        exponent >>= 1;
+    }
+    result
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SpawnRules {
+    reset_timer: usize,
+    newborn_timer: usize,
+    // Fish die after spawning this many times
+    lifespan: Option<usize>,
+    offspring: u32,
+}
+
+impl Default for SpawnRules {
+    fn default() -> Self {
+        Self {
+            reset_timer: 6,
+            newborn_timer: 8,
+            lifespan: None,
+            offspring: 1,
+        }
+    }
+}
+
+impl SpawnRules {
+    // Applies a "name=value" setting, returns false if it isn't a rule
+    fn apply(&mut self, setting: &str) -> bool {
+        let (name, value) = match setting.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => return false,
+        };
+
+        let parse = |value: &str| value.parse::<usize>().expect("Invalid spawn rule value");
+        match name {
+            "reset" => self.reset_timer = parse(value),
+            "newborn" => self.newborn_timer = parse(value),
+            "lifespan" => {
+                let lifespan = parse(value);
+                if lifespan < 1 {
+                    panic!("Lifespan must be at least 1");
                 }
-            })
-            .collect::<Vec<usize>>();
-        fishes.extend(new_fishes);
+                self.lifespan = Some(lifespan)
+            }
+            "offspring" => self.offspring = parse(value) as u32,
+            _ => return false,
+        }
+        true
     }
+}
 
-    fishes.len()
+// Number of fish per timer value and per number of times they have spawned
+#[derive(Debug, Clone)]
+struct School {
+    rules: SpawnRules,
+    num_timers: usize,
+    buckets: Vec<BigUint>,
 }
 
-fn solve_part2(inputfile: String) -> usize {
+impl School {
+    fn new(rules: SpawnRules, timers: &[usize]) -> Self {
+        let num_timers = 1 + timers
+            .iter()
+            .copied()
+            .chain([rules.reset_timer, rules.newborn_timer])
+            .max()
+            .unwrap();
+        let num_cycles = rules.lifespan.unwrap_or(1);
+
+        let mut buckets = vec![BigUint::from(0u32); num_timers * num_cycles];
+        for &timer in timers {
+            buckets[timer] += 1u32;
+        }
+
+        Self {
+            rules,
+            num_timers,
+            buckets,
+        }
+    }
+
+    // Every (from, to, factor) move of fish between buckets over one day
+    fn transitions(&self) -> Vec<(usize, usize, u32)> {
+        let mut transitions = vec![];
+
+        for (index, _) in self.buckets.iter().enumerate() {
+            let (timer, cycle) = (index % self.num_timers, index / self.num_timers);
+
+            if timer > 0 {
+                transitions.push((index, index - 1, 1));
+                continue;
+            }
+
+            transitions.push((index, self.rules.newborn_timer, self.rules.offspring));
+
+            let next_cycle = match self.rules.lifespan {
+                Some(lifespan) if cycle + 1 >= lifespan => continue,
+                Some(_) => cycle + 1,
+                None => cycle,
+            };
+            transitions.push((
+                index,
+                next_cycle * self.num_timers + self.rules.reset_timer,
+                1,
+            ));
+        }
+        transitions
+    }
+
+    fn transition_matrix(&self) -> Matrix {
+        let mut matrix = vec![vec![BigUint::from(0u32); self.buckets.len()]; self.buckets.len()];
+        for (from, to, factor) in self.transitions() {
+            matrix[to][from] += factor;
+        }
+        matrix
+    }
+
+    fn step(&mut self, precision: &Precision) {
+        let mut buckets = vec![BigUint::from(0u32); self.buckets.len()];
+        for (from, to, factor) in self.transitions() {
+            buckets[to] += &self.buckets[from] * factor;
+        }
+        self.buckets = buckets
+            .into_iter()
+            .map(|count| precision.reduce(count))
+            .collect();
+    }
+
+    fn population(&self) -> BigUint {
+        self.buckets.iter().sum()
+    }
+
+    fn population_after(&self, days: u64, precision: &Precision) -> BigUint {
+        let transition = matrix_power(&self.transition_matrix(), days, precision);
+
+        precision.reduce(
+            transition
+                .iter()
+                .flat_map(|row| row.iter().zip(self.buckets.iter()))
+                .map(|(factor, count)| factor * count)
+                .sum(),
+        )
+    }
+}
+
+// The input may start with a header line of rules, e.g. "reset=6 newborn=8 lifespan=3".
+// Rules given as options are applied on top of the header.
+fn parse_input(inputfile: String, options: &[&str]) -> School {
     let contents =
         std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
 
-    let mut fish_buckets = contents
-        .lines()
+    let mut rules = SpawnRules::default();
+    let mut lines = contents.lines().peekable();
+    if let Some(header) = lines.next_if(|line| line.contains('=')) {
+        for setting in header.split_whitespace() {
+            if !rules.apply(setting) {
+                panic!("Unknown spawn rule {}", setting);
+            }
+        }
+    }
+    for setting in options {
+        if !rules.apply(setting) {
+            panic!("Unknown spawn rule --{}", setting);
+        }
+    }
+
+    let timers = lines
         .next()
         .unwrap()
         .split(',')
-        .map(|cycletime| cycletime.parse::<usize>().unwrap())
-        .sorted()
-        .into_iter()
-        .dedup_with_count()
-        .map(|(num_fish, cycle_time)| (cycle_time, num_fish))
-        .collect::<HashMap<usize, usize>>();
-
-    for _ in 0..256 {
-        let new_fish = match fish_buckets.get(&0) {
-            Some(&count) => count,
-            None => 0,
-        };
+        .map(|cycletime| cycletime.trim().parse::<usize>().unwrap())
+        .collect::<Vec<usize>>();
 
-        for cycle_time in 0..8 {
-            let next_fish_count = match fish_buckets.get(&(cycle_time + 1)) {
-                Some(&count) => count,
-                None => 0,
-            };
+    School::new(rules, &timers)
+}
 
-            fish_buckets
-                .entry(cycle_time)
-                .and_modify(|e| *e = next_fish_count)
-                .or_insert(next_fish_count);
-        }
+fn solve_part1(inputfile: String, options: &[&str]) -> BigUint {
+    let school = parse_input(inputfile, options);
 
-        fish_buckets
-            .entry(6)
-            .and_modify(|e| *e += new_fish)
-            .or_insert(0);
-        fish_buckets
-            .entry(8)
-            .and_modify(|e| *e = new_fish)
-            .or_insert(0);
-    }
+    school.population_after(80, &Precision::Exact)
+}
+
+fn solve_part2(inputfile: String, options: &[&str]) -> BigUint {
+    let school = parse_input(inputfile, options);
+
+    school.population_after(256, &Precision::Exact)
+}
 
-    fish_buckets.values().sum()
+fn print_population_series(inputfile: String, options: &[&str], days: u64, precision: &Precision) {
+    let mut school = parse_input(inputfile, options);
+
+    println!("day {}: {}", 0, school.population());
+    for day in 1..=days {
+        school.step(precision);
+        println!("day {}: {}", day, school.population());
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    println!("Part1: {}", solve_part1(args[1].to_string()));
-    println!("Part2: {}", solve_part2(args[1].to_string()));
+
+    // Rules are given as options, e.g. "--reset=6 --newborn=8 --lifespan=3 --offspring=2"
+    let (options, positional): (Vec<&str>, Vec<&str>) = args
+        .iter()
+        .skip(2)
+        .map(|arg| arg.as_str())
+        .partition(|arg| arg.starts_with("--"));
+    let (options, flags): (Vec<&str>, Vec<&str>) = options
+        .iter()
+        .map(|option| option.trim_start_matches("--"))
+        .partition(|option| option.contains('='));
+    if let Some(flag) = flags.iter().find(|&&flag| flag != "series") {
+        panic!("Unknown option --{}", flag);
+    }
+
+    println!("Part1: {}", solve_part1(args[1].to_string(), &options));
+    println!("Part2: {}", solve_part2(args[1].to_string(), &options));
+
+    // Any number of days, optionally followed by a modulus, e.g. "1000000000000000000 1000000007"
+    if let Some(days) = positional.first() {
+        let days = days.parse::<u64>().expect("Invalid number of days");
+        let precision = match positional.get(1) {
+            Some(modulus) => {
+                Precision::Modulo(modulus.parse::<BigUint>().expect("Invalid modulus"))
+            }
+            None => Precision::Exact,
+        };
+
+        if flags.contains(&"series") {
+            print_population_series(args[1].to_string(), &options, days, &precision);
+        } else {
+            let school = parse_input(args[1].to_string(), &options);
+            println!(
+                "Population after {} days: {}",
+                days,
+                school.population_after(days, &precision)
+            );
+        }
+    }
 }
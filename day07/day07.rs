@@ -1,60 +1,575 @@
 use std::env;
 
-fn solve_part1(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+// All positions passed to a cost function are expected to be sorted
+trait FuelCost {
+    fn cost(&self, distance: i64) -> i64;
 
-    let positions = contents
-        .lines()
-        .next()
-        .unwrap()
-        .split(',')
-        .map(|position| position.parse::<usize>().unwrap())
-        .collect::<Vec<usize>>();
-    let max_position = positions.iter().max().unwrap();
-
-    let mut min_fuel_cost = 0;
-    for aligned_position in 0..*max_position {
-        let fuel_cost = positions.iter().fold(0, |total_fuel, position| {
-            total_fuel + (*position as i64 - aligned_position as i64).abs()
-        });
-        if fuel_cost < min_fuel_cost || min_fuel_cost == 0 {
-            min_fuel_cost = fuel_cost;
+    fn total(&self, positions: &[i64], target: i64) -> i64 {
+        positions
+            .iter()
+            .map(|position| self.cost((position - target).abs()))
+            .sum()
+    }
+
+    // Any convex cost has a single valley between the outermost crabs, so a
+    // ternary search will find it
+    fn optimal_position(&self, positions: &[i64]) -> i64 {
+        let (mut low, mut high) = (positions[0], positions[positions.len() - 1]);
+
+        while high - low > 2 {
+            let first_third = low + (high - low) / 3;
+            let second_third = high - (high - low) / 3;
+
+            if self.total(positions, first_third) <= self.total(positions, second_third) {
+                high = second_third;
+            } else {
+                low = first_third;
+            }
         }
+
+        (low..=high)
+            .min_by_key(|&target| self.total(positions, target))
+            .unwrap()
+    }
+
+    fn cost_curve(&self, positions: &[i64]) -> Vec<(i64, i64)> {
+        (positions[0]..=positions[positions.len() - 1])
+            .map(|target| (target, self.total(positions, target)))
+            .collect()
     }
 
-    min_fuel_cost as usize
+    // Best position and fuel for aligning the crabs from start to end. This visits
+    // every crab of the group for every target it tries, so only custom costs
+    // use it, the others evaluate it from prefix sums.
+    fn group_fuel(&self, crabs: &SortedPositions, start: usize, end: usize) -> (i64, i64) {
+        let positions = &crabs.positions[start..end];
+        let position = self.optimal_position(positions);
+        (position, self.total(positions, position))
+    }
 }
 
-fn solve_part2(inputfile: String) -> usize {
+// Sorted positions with prefix sums, to get the fuel of any group of
+// neighbouring crabs without visiting every crab
+struct SortedPositions {
+    positions: Vec<i64>,
+    sums: Vec<i64>,
+    square_sums: Vec<i64>,
+}
+
+impl SortedPositions {
+    fn new(positions: Vec<i64>) -> Self {
+        let mut sums = vec![0];
+        let mut square_sums = vec![0];
+        for position in &positions {
+            sums.push(sums.last().unwrap() + position);
+            square_sums.push(square_sums.last().unwrap() + position * position);
+        }
+
+        Self {
+            positions,
+            sums,
+            square_sums,
+        }
+    }
+
+    fn distance_sum(&self, start: usize, end: usize, target: i64) -> i64 {
+        let split =
+            start + self.positions[start..end].partition_point(|&position| position <= target);
+        let (num_left, num_right) = ((split - start) as i64, (end - split) as i64);
+
+        num_left * target - (self.sums[split] - self.sums[start])
+            + (self.sums[end] - self.sums[split])
+            - num_right * target
+    }
+
+    fn square_distance_sum(&self, start: usize, end: usize, target: i64) -> i64 {
+        let count = (end - start) as i64;
+        let sum = self.sums[end] - self.sums[start];
+        let square_sum = self.square_sums[end] - self.square_sums[start];
+
+        count * target * target - 2 * target * sum + square_sum
+    }
+
+    fn mean_neighbourhood(&self, start: usize, end: usize) -> std::ops::RangeInclusive<i64> {
+        let mean = (self.sums[end] - self.sums[start]) / (end - start) as i64;
+        (mean - 1).max(self.positions[start])..=(mean + 1).min(self.positions[end - 1])
+    }
+}
+
+struct LinearCost;
+struct TriangularCost;
+struct QuadraticCost;
+// Fuel distance^exponent, with prefix sums of the powers of every crab's distance
+// from the leftmost one
+struct PowerCost {
+    exponent: u32,
+    offset: i64,
+    power_sums: Vec<Vec<i128>>,
+}
+struct CustomCost<F: Fn(i64) -> i64>(F);
+
+impl FuelCost for LinearCost {
+    fn cost(&self, distance: i64) -> i64 {
+        distance
+    }
+
+    fn optimal_position(&self, positions: &[i64]) -> i64 {
+        positions[(positions.len() - 1) / 2]
+    }
+
+    // Moving one step right costs one more for every crab at or left of the target,
+    // and one less for every crab right of it
+    fn cost_curve(&self, positions: &[i64]) -> Vec<(i64, i64)> {
+        let mut curve = vec![];
+        let mut fuel = self.total(positions, positions[0]);
+        let mut num_left = 0;
+
+        for target in positions[0]..=positions[positions.len() - 1] {
+            curve.push((target, fuel));
+
+            while num_left < positions.len() && positions[num_left] <= target {
+                num_left += 1;
+            }
+            fuel += num_left as i64 - (positions.len() - num_left) as i64;
+        }
+        curve
+    }
+
+    fn group_fuel(&self, crabs: &SortedPositions, start: usize, end: usize) -> (i64, i64) {
+        let median = crabs.positions[start + (end - start - 1) / 2];
+        (median, crabs.distance_sum(start, end, median))
+    }
+}
+
+impl FuelCost for TriangularCost {
+    fn cost(&self, distance: i64) -> i64 {
+        distance * (distance + 1) / 2
+    }
+
+    // The optimum is always within half a step of the mean
+    fn optimal_position(&self, positions: &[i64]) -> i64 {
+        let mean = positions.iter().sum::<i64>() / positions.len() as i64;
+
+        ((mean - 1).max(positions[0])..=(mean + 1).min(positions[positions.len() - 1]))
+            .min_by_key(|&target| self.total(positions, target))
+            .unwrap()
+    }
+
+    fn cost_curve(&self, positions: &[i64]) -> Vec<(i64, i64)> {
+        let mut curve = vec![];
+        let mut fuel = self.total(positions, positions[0]);
+        let (mut num_left, mut left_sum) = (0, 0);
+        let total_sum = positions.iter().sum::<i64>();
+
+        for target in positions[0]..=positions[positions.len() - 1] {
+            curve.push((target, fuel));
+
+            while num_left < positions.len() && positions[num_left] <= target {
+                left_sum += positions[num_left];
+                num_left += 1;
+            }
+            let num_right = (positions.len() - num_left) as i64;
+            let right_sum = total_sum - left_sum;
+
+            fuel += num_left as i64 * (target + 1) - left_sum - (right_sum - num_right * target);
+        }
+        curve
+    }
+
+    fn group_fuel(&self, crabs: &SortedPositions, start: usize, end: usize) -> (i64, i64) {
+        crabs
+            .mean_neighbourhood(start, end)
+            .map(|target| {
+                let fuel = (crabs.square_distance_sum(start, end, target)
+                    + crabs.distance_sum(start, end, target))
+                    / 2;
+                (target, fuel)
+            })
+            .min_by_key(|&(_, fuel)| fuel)
+            .unwrap()
+    }
+}
+
+impl FuelCost for QuadraticCost {
+    fn cost(&self, distance: i64) -> i64 {
+        distance * distance
+    }
+
+    fn cost_curve(&self, positions: &[i64]) -> Vec<(i64, i64)> {
+        let count = positions.len() as i64;
+        let sum = positions.iter().sum::<i64>();
+        let square_sum = positions
+            .iter()
+            .map(|position| position * position)
+            .sum::<i64>();
+
+        (positions[0]..=positions[positions.len() - 1])
+            .map(|target| {
+                (
+                    target,
+                    count * target * target - 2 * target * sum + square_sum,
+                )
+            })
+            .collect()
+    }
+
+    fn group_fuel(&self, crabs: &SortedPositions, start: usize, end: usize) -> (i64, i64) {
+        crabs
+            .mean_neighbourhood(start, end)
+            .map(|target| (target, crabs.square_distance_sum(start, end, target)))
+            .min_by_key(|&(_, fuel)| fuel)
+            .unwrap()
+    }
+}
+
+impl PowerCost {
+    // Refuses exponents where aligning at the worst position overflows the fuel
+    fn new(exponent: u32, positions: &[i64]) -> Self {
+        let offset = positions[0];
+        let range = positions[positions.len() - 1] - offset;
+        let worst_fuel = (range as i128)
+            .checked_pow(exponent)
+            .and_then(|fuel| fuel.checked_mul(positions.len() as i128))
+            .filter(|&fuel| fuel <= i64::MAX as i128);
+        if exponent >= 64 || worst_fuel.is_none() {
+            panic!(
+                "pow:{} overflows the fuel for crabs {} apart",
+                exponent, range
+            );
+        }
+
+        let power_sums = (0..=exponent)
+            .map(|power| {
+                let mut power_sums = vec![0];
+                for &position in positions {
+                    power_sums.push(
+                        power_sums.last().unwrap() + ((position - offset) as i128).pow(power),
+                    );
+                }
+                power_sums
+            })
+            .collect();
+
+        Self {
+            exponent,
+            offset,
+            power_sums,
+        }
+    }
+
+    // Sum of |position - target|^exponent over the crabs from start to end,
+    // expanding (position - target)^exponent binomially on both sides of the target
+    fn power_distance_sum(
+        &self,
+        crabs: &SortedPositions,
+        start: usize,
+        end: usize,
+        target: i64,
+    ) -> i64 {
+        let split =
+            start + crabs.positions[start..end].partition_point(|&position| position <= target);
+        let exponent = self.exponent as usize;
+        let target = (target - self.offset) as i128;
+
+        let mut binomial = 1i128;
+        let (mut left, mut right) = (0i128, 0i128);
+        for power in 0..=exponent {
+            let factor = binomial * (-target).pow((exponent - power) as u32);
+            left += factor * (self.power_sums[power][split] - self.power_sums[power][start]);
+            right += factor * (self.power_sums[power][end] - self.power_sums[power][split]);
+            binomial = binomial * (exponent - power) as i128 / (power + 1) as i128;
+        }
+
+        // Left of the target the distance is target - position
+        if exponent % 2 == 1 {
+            left = -left;
+        }
+        i64::try_from(left + right).expect("Fuel overflows")
+    }
+}
+
+impl FuelCost for PowerCost {
+    fn cost(&self, distance: i64) -> i64 {
+        distance.pow(self.exponent)
+    }
+
+    // The fuel is convex in the target, so a ternary search over the prefix sums works
+    fn group_fuel(&self, crabs: &SortedPositions, start: usize, end: usize) -> (i64, i64) {
+        let fuel = |target: i64| self.power_distance_sum(crabs, start, end, target);
+        let (mut low, mut high) = (crabs.positions[start], crabs.positions[end - 1]);
+
+        while high - low > 2 {
+            let first_third = low + (high - low) / 3;
+            let second_third = high - (high - low) / 3;
+
+            if fuel(first_third) <= fuel(second_third) {
+                high = second_third;
+            } else {
+                low = first_third;
+            }
+        }
+
+        (low..=high)
+            .map(|target| (target, fuel(target)))
+            .min_by_key(|&(_, fuel)| fuel)
+            .unwrap()
+    }
+}
+
+impl<F: Fn(i64) -> i64> FuelCost for CustomCost<F> {
+    fn cost(&self, distance: i64) -> i64 {
+        (self.0)(distance)
+    }
+}
+
+#[derive(Debug)]
+struct Alignment {
+    position: i64,
+    fuel: i64,
+    cost_curve: Vec<(i64, i64)>,
+}
+
+fn align(positions: &[i64], fuel_cost: &dyn FuelCost) -> Alignment {
+    let position = fuel_cost.optimal_position(positions);
+
+    Alignment {
+        position,
+        fuel: fuel_cost.total(positions, position),
+        cost_curve: fuel_cost.cost_curve(positions),
+    }
+}
+
+#[derive(Debug)]
+struct Rendezvous {
+    position: i64,
+    num_crabs: usize,
+    fuel: i64,
+}
+
+// With a convex cost every group is a run of neighbouring crabs, so the best
+// split into groups is found by dynamic programming over the sorted positions
+fn align_groups(
+    crabs: &SortedPositions,
+    fuel_cost: &dyn FuelCost,
+    num_groups: usize,
+) -> (i64, Vec<Rendezvous>) {
+    let num_crabs = crabs.positions.len();
+    let num_groups = num_groups.clamp(1, num_crabs);
+
+    let group_fuel = (0..num_crabs)
+        .map(|start| {
+            (start + 1..=num_crabs)
+                .map(|end| fuel_cost.group_fuel(crabs, start, end))
+                .collect::<Vec<(i64, i64)>>()
+        })
+        .collect::<Vec<Vec<(i64, i64)>>>();
+    let fuel_between = |start: usize, end: usize| group_fuel[start][end - start - 1];
+
+    // best_fuel[groups][end] is the least fuel for the first end crabs in that many groups
+    let mut best_fuel = vec![vec![i64::MAX; num_crabs + 1]; num_groups + 1];
+    let mut group_start = vec![vec![0; num_crabs + 1]; num_groups + 1];
+    best_fuel[0][0] = 0;
+
+    for groups in 1..=num_groups {
+        for end in groups..=num_crabs {
+            for start in groups - 1..end {
+                if best_fuel[groups - 1][start] == i64::MAX {
+                    continue;
+                }
+
+                let fuel = best_fuel[groups - 1][start] + fuel_between(start, end).1;
+                if fuel < best_fuel[groups][end] {
+                    best_fuel[groups][end] = fuel;
+                    group_start[groups][end] = start;
+                }
+            }
+        }
+    }
+
+    let mut rendezvous = vec![];
+    let mut end = num_crabs;
+    for groups in (1..=num_groups).rev() {
+        let start = group_start[groups][end];
+        let (position, fuel) = fuel_between(start, end);
+        rendezvous.push(Rendezvous {
+            position,
+            num_crabs: end - start,
+            fuel,
+        });
+        end = start;
+    }
+    rendezvous.reverse();
+
+    (best_fuel[num_groups][num_crabs], rendezvous)
+}
+
+fn parse_fuel_cost(name: &str, positions: &[i64]) -> Box<dyn FuelCost> {
+    match name.split_once(':') {
+        None if name == "linear" => Box::new(LinearCost),
+        None if name == "triangular" => Box::new(TriangularCost),
+        None if name == "quadratic" => Box::new(QuadraticCost),
+        Some(("pow", exponent)) => {
+            let exponent = exponent.parse::<u32>().expect("Invalid exponent");
+            Box::new(PowerCost::new(exponent, positions))
+        }
+        // "poly:a,b,c" costs a*d + b*d^2 + c*d^3, non-negative weights keep it convex
+        Some(("poly", weights)) => {
+            let weights = weights
+                .split(',')
+                .map(|weight| weight.parse::<u32>().expect("Invalid weight") as i64)
+                .collect::<Vec<i64>>();
+            Box::new(CustomCost(move |distance: i64| {
+                weights.iter().rev().fold(0i64, |fuel, weight| {
+                    fuel.checked_add(*weight)
+                        .and_then(|fuel| fuel.checked_mul(distance))
+                        .expect("Fuel cost overflows")
+                })
+            }))
+        }
+        _ => panic!("Unknown fuel cost {}", name),
+    }
+}
+
+fn parse_input(inputfile: String) -> Vec<i64> {
     let contents =
         std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
 
-    let positions = contents
+    let mut positions = contents
         .lines()
         .next()
         .unwrap()
         .split(',')
-        .map(|position| position.parse::<usize>().unwrap())
-        .collect::<Vec<usize>>();
-    let max_position = positions.iter().max().unwrap();
-
-    let mut min_fuel_cost = 0;
-    for aligned_position in 0..*max_position {
-        let fuel_cost = positions.iter().fold(0, |total_fuel, position| {
-            let distance = (*position as i64 - aligned_position as i64).abs();
-            total_fuel + (distance * (distance + 1) / 2)
-        });
-        if fuel_cost < min_fuel_cost || min_fuel_cost == 0 {
-            min_fuel_cost = fuel_cost;
-        }
-    }
+        .map(|position| position.parse::<i64>().unwrap())
+        .collect::<Vec<i64>>();
+    positions.sort_unstable();
+    positions
+}
+
+fn solve_part1(inputfile: String) -> usize {
+    let positions = parse_input(inputfile);
 
-    min_fuel_cost as usize
+    align(&positions, &LinearCost).fuel as usize
+}
+
+fn solve_part2(inputfile: String) -> usize {
+    let positions = parse_input(inputfile);
+
+    align(&positions, &TriangularCost).fuel as usize
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     println!("Part1: {}", solve_part1(args[1].to_string()));
     println!("Part2: {}", solve_part2(args[1].to_string()));
+
+    // "--cost=linear|triangular|quadratic|pow:K|poly:A,B,.." aligns with another cost,
+    // "--curve" prints the fuel needed for every position and "--groups=K"
+    // splits the crabs over K positions
+    let cost_name = args.iter().find_map(|arg| arg.strip_prefix("--cost="));
+    let num_groups = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--groups="))
+        .map(|groups| groups.parse::<usize>().expect("Invalid number of groups"));
+
+    if let Some(name) = cost_name {
+        let positions = parse_input(args[1].to_string());
+        let alignment = align(&positions, parse_fuel_cost(name, &positions).as_ref());
+
+        println!(
+            "Alignment with {} cost: position {}, fuel {}",
+            name, alignment.position, alignment.fuel
+        );
+
+        if args.iter().any(|arg| arg == "--curve") {
+            for (position, fuel) in alignment.cost_curve {
+                println!("{} {}", position, fuel);
+            }
+        }
+    }
+
+    if let Some(num_groups) = num_groups {
+        let name = cost_name.unwrap_or("linear");
+        let crabs = SortedPositions::new(parse_input(args[1].to_string()));
+        let fuel_cost = parse_fuel_cost(name, &crabs.positions);
+        let (total_fuel, rendezvous) = align_groups(&crabs, fuel_cost.as_ref(), num_groups);
+
+        println!(
+            "Alignment in {} groups with {} cost: fuel {}",
+            rendezvous.len(),
+            name,
+            total_fuel
+        );
+        for group in rendezvous {
+            println!(
+                "  position {}: {} crabs, fuel {}",
+                group.position, group.num_crabs, group.fuel
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_positions(count: usize) -> Vec<i64> {
+        let mut seed: u64 = 12345;
+        let mut positions = (0..count)
+            .map(|_| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (seed >> 33) as i64 % 50 - 20
+            })
+            .collect::<Vec<i64>>();
+        positions.sort_unstable();
+        positions
+    }
+
+    #[test]
+    fn power_distance_sum_matches_brute_force() {
+        let crabs = SortedPositions::new(generate_positions(30));
+
+        for exponent in 0..=5 {
+            let power_cost = PowerCost::new(exponent, &crabs.positions);
+            for start in 0..crabs.positions.len() {
+                for end in start + 1..=crabs.positions.len() {
+                    for target in crabs.positions[0] - 2..=crabs.positions[29] + 2 {
+                        let brute_force = crabs.positions[start..end]
+                            .iter()
+                            .map(|position| (position - target).abs().pow(exponent))
+                            .sum::<i64>();
+                        assert_eq!(
+                            power_cost.power_distance_sum(&crabs, start, end, target),
+                            brute_force
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn power_group_fuel_matches_ternary_search() {
+        let crabs = SortedPositions::new(generate_positions(30));
+
+        for exponent in 1..=4 {
+            let power_cost = PowerCost::new(exponent, &crabs.positions);
+            let custom_cost = CustomCost(|distance: i64| distance.pow(exponent));
+            for start in 0..crabs.positions.len() {
+                for end in start + 1..=crabs.positions.len() {
+                    assert_eq!(
+                        power_cost.group_fuel(&crabs, start, end).1,
+                        custom_cost.group_fuel(&crabs, start, end).1
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows")]
+    fn power_cost_rejects_overflowing_exponents() {
+        PowerCost::new(6, &[0, 1000, 2000]);
+    }
 }
@@ -1,393 +1,359 @@
-use itertools::Itertools;
-use std::char;
-use std::collections::HashMap;
 use std::env;
 
-fn solve_part1(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
-
-    let num_easy = contents.lines().fold(0, |total_acc, line| {
-        let decoded: usize = line
-            .split(" | ")
-            .nth(1)
-            .unwrap()
-            .split(' ')
-            .filter_map(|output| match output.len() {
-                2 => Some(1),
-                3 => Some(1),
-                4 => Some(1),
-                7 => Some(1),
-                _ => Some(0),
-            })
-            .sum();
-
-        total_acc + decoded
-    });
-    num_easy
+//  0000
+// 1    2
+// 1    2
+//  3333
+// 4    5
+// 4    5
+//  6666
+const SEVEN_SEGMENT_DIGITS: [(char, &[usize]); 10] = [
+    ('0', &[0, 1, 2, 4, 5, 6]),
+    ('1', &[2, 5]),
+    ('2', &[0, 2, 3, 4, 6]),
+    ('3', &[0, 2, 3, 5, 6]),
+    ('4', &[1, 2, 3, 5]),
+    ('5', &[0, 1, 3, 5, 6]),
+    ('6', &[0, 1, 3, 4, 5, 6]),
+    ('7', &[0, 2, 5]),
+    ('8', &[0, 1, 2, 3, 4, 5, 6]),
+    ('9', &[0, 1, 2, 3, 5, 6]),
+];
+
+// Sets of wires and segments are bitmasks, wire 'a' is bit 0
+type Signals = u32;
+
+#[derive(Debug)]
+struct SegmentDisplay {
+    segment_names: Vec<String>,
+    glyphs: Vec<(char, Signals)>,
 }
 
-fn solve_part2(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
-
-    //  0000
-    // 1    2
-    // 1    2
-    //  3333
-    // 4    5
-    // 4    5
-    //  6666
-    let full_mapping = HashMap::from([
-        (0, vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']),
-        (1, vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']),
-        (2, vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']),
-        (3, vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']),
-        (4, vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']),
-        (5, vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']),
-        (6, vec!['a', 'b', 'c', 'd', 'e', 'f', 'g']),
-    ]);
-
-    let number_mapping = HashMap::from([
-        (0, vec![0, 1, 2, 4, 5, 6]),
-        (1, vec![2, 5]),
-        (2, vec![0, 2, 3, 4, 6]),
-        (3, vec![0, 2, 3, 5, 6]),
-        (4, vec![1, 2, 3, 5]),
-        (5, vec![0, 1, 3, 5, 6]),
-        (6, vec![0, 1, 3, 4, 5, 6]),
-        (7, vec![0, 2, 5]),
-        (8, vec![0, 1, 2, 3, 4, 5, 6]),
-        (9, vec![0, 1, 2, 3, 5, 6]),
-    ]);
-
-    let mut reverse_number_mapping = HashMap::new();
-
-    for (key, values) in &number_mapping {
-        for value in values {
-            reverse_number_mapping
-                .entry(value)
-                .and_modify(|e: &mut Vec<usize>| (*e).push(*key))
-                .or_insert(vec![*key]);
+impl SegmentDisplay {
+    fn seven_segment() -> Self {
+        Self {
+            segment_names: (0..7).map(|segment| segment.to_string()).collect(),
+            glyphs: SEVEN_SEGMENT_DIGITS
+                .iter()
+                .map(|(symbol, segments)| {
+                    (
+                        *symbol,
+                        segments.iter().fold(0, |acc, segment| acc | 1 << segment),
+                    )
+                })
+                .collect(),
         }
     }
 
-    let mut number_length_mapping = HashMap::new();
+    // A definition starts with a "segments" line naming every segment, followed
+    // by one line per glyph with its symbol and lit segments, e.g. "7 a b c"
+    fn from_file(definitionfile: &str) -> Self {
+        let contents = std::fs::read_to_string(definitionfile)
+            .expect("Something went wrong reading the display definition");
+
+        let mut lines = contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let segment_names = lines
+            .next()
+            .and_then(|line| line.strip_prefix("segments"))
+            .expect("Display definition must start with the segment names")
+            .split_whitespace()
+            .map(|name| name.to_string())
+            .collect::<Vec<String>>();
+
+        let glyphs = lines
+            .map(|line| {
+                let mut tokens = line.split_whitespace();
+                let symbol = tokens.next().unwrap().chars().next().unwrap();
+                let segments = tokens.fold(0, |acc, name| {
+                    let segment = segment_names
+                        .iter()
+                        .position(|segment_name| segment_name == name)
+                        .unwrap_or_else(|| panic!("Unknown segment {} for {}", name, symbol));
+                    acc | 1 << segment
+                });
+                (symbol, segments)
+            })
+            .collect();
 
-    for (key, values) in &number_mapping {
-        number_length_mapping
-            .entry(values.len())
-            .and_modify(|e: &mut Vec<usize>| (*e).push(*key))
-            .or_insert(vec![*key]);
+        Self {
+            segment_names,
+            glyphs,
+        }
     }
 
-    for (tile, numbers) in reverse_number_mapping.iter() {
-        println!("tile[{}] in numbers: {:?}", tile, numbers);
+    fn num_segments(&self) -> usize {
+        self.segment_names.len()
     }
-    for (number, tiles) in number_mapping.iter() {
-        println!("number[{}] : {:?}", number, tiles);
+
+    // Glyphs that can be told apart from the others by their number of segments
+    fn unique_lengths(&self) -> Vec<u32> {
+        self.glyphs
+            .iter()
+            .map(|(_, glyph)| glyph.count_ones())
+            .filter(|&length| {
+                self.glyphs
+                    .iter()
+                    .filter(|(_, glyph)| glyph.count_ones() == length)
+                    .count()
+                    == 1
+            })
+            .collect()
     }
-    for (length, numbers) in number_length_mapping.iter() {
-        println!("length[{}] : {:?}", length, numbers);
+
+    fn symbol(&self, segments: Signals) -> Option<char> {
+        self.glyphs
+            .iter()
+            .find(|(_, glyph)| *glyph == segments)
+            .map(|(symbol, _)| *symbol)
     }
+}
 
-    contents.lines().fold(0, |acc, line| {
-        let mut mapping = full_mapping.clone();
-        let decoded = line
-            .split(" | ")
-            .map(|signals| signals.split(' ').collect::<Vec<&str>>())
-            .collect::<Vec<Vec<&str>>>();
+#[derive(Debug, PartialEq)]
+enum DecodeError {
+    // No wiring makes every pattern a valid glyph
+    Inconsistent,
+    // More than one wiring fits the patterns
+    Ambiguous,
+    UnknownOutput(String),
+}
 
-        let inputs = decoded.iter().nth(0).unwrap();
-        let outputs = decoded
-            .iter()
-            .nth(1)
-            .unwrap()
+fn parse_signals(pattern: &str) -> Signals {
+    pattern
+        .chars()
+        .fold(0, |acc, wire| acc | 1 << (wire as u32 - 'a' as u32))
+}
+
+// Maps every wire onto the segment it is connected to, wires that are never
+// lit are left unknown
+#[derive(Debug, Clone, PartialEq)]
+struct Wiring(Vec<Option<usize>>);
+
+impl Wiring {
+    fn apply(&self, signals: Signals) -> Option<Signals> {
+        self.0
             .iter()
-            .map(|pattern| pattern.chars().sorted().collect::<String>())
-            .collect::<Vec<String>>();
+            .enumerate()
+            .filter(|(wire, _)| signals & 1 << wire != 0)
+            .try_fold(0, |acc, (_, segment)| {
+                segment.map(|segment| acc | 1 << segment)
+            })
+    }
 
-        let sorted_inputs = inputs
+    // e.g. "a=2 b=5 c=?", with the display's segment names
+    fn describe(&self, display: &SegmentDisplay) -> String {
+        self.0
             .iter()
-            .map(|pattern| pattern.chars().sorted().collect::<String>())
-            .sorted_by(|a, b| Ord::cmp(&b.len(), &a.len()))
-            .collect::<Vec<String>>();
+            .enumerate()
+            .map(|(wire, segment)| {
+                let wire = (b'a' + wire as u8) as char;
+                match segment {
+                    Some(segment) => format!("{}={}", wire, display.segment_names[*segment]),
+                    None => format!("{}=?", wire),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
 
-        let mut deduced_number_mapping = HashMap::new();
+struct WiringSolver<'a> {
+    display: &'a SegmentDisplay,
+    patterns: &'a [Signals],
+    outputs: &'a [Signals],
+    candidates: Vec<Signals>,
+    lit_wires: Signals,
+    solution: Option<Wiring>,
+    is_ambiguous: bool,
+}
 
-        for pattern in &sorted_inputs {
-            let signals = pattern.chars().collect::<Vec<char>>();
-            match pattern.len() {
-                2 => {
-                    deduced_number_mapping.insert(1, pattern.clone());
-                    for (key, possibilities) in mapping.iter_mut() {
-                        let filtered_possibilities: Vec<char> = possibilities
-                            .iter()
-                            .filter(|signal| !signals.contains(signal)).copied()
-                            .collect();
-
-                        *possibilities = match *key {
-                            2 => signals.clone(),
-                            5 => signals.clone(),
-                            _ => filtered_possibilities.clone(),
-                        }
-                    }
-                }
-                3 => {
-                    deduced_number_mapping.insert(7, pattern.clone());
-                    for (key, possibilities) in mapping.iter_mut() {
-                        let filtered_possibilities: Vec<char> = possibilities
-                            .iter()
-                            .filter(|signal| !signals.contains(signal)).copied()
-                            .collect();
-
-                        *possibilities = match *key {
-                            0 => signals.clone(),
-                            2 => signals.clone(),
-                            5 => signals.clone(),
-                            _ => filtered_possibilities.clone(),
-                        }
-                    }
-                }
-                4 => {
-                    deduced_number_mapping.insert(4, pattern.clone());
-                    for (key, possibilities) in mapping.iter_mut() {
-                        let filtered_possibilities: Vec<char> = possibilities
-                            .iter()
-                            .filter(|signal| !signals.contains(signal)).copied()
-                            .collect();
-
-                        *possibilities = match *key {
-                            1 => signals.clone(),
-                            2 => signals.clone(),
-                            3 => signals.clone(),
-                            5 => signals.clone(),
-                            _ => filtered_possibilities.clone(),
-                        }
-                    }
-                }
-                5 => {
-                    for place in [0, 3, 6] {
-                        mapping.entry(place).and_modify(|possibilities| {
-                            *possibilities = possibilities
-                                .iter()
-                                .filter(|signal| signals.contains(signal)).copied()
-                                .collect::<Vec<char>>()
-                        });
-                    }
-                }
-                6 => {
-                    for place in [0, 1, 5, 6] {
-                        mapping.entry(place).and_modify(|possibilities| {
-                            *possibilities = possibilities
-                                .iter()
-                                .filter(|signal| signals.contains(signal)).copied()
-                                .collect::<Vec<char>>()
-                        });
-                    }
-                }
-                7 => {
-                    deduced_number_mapping.insert(8, pattern.clone());
+impl<'a> WiringSolver<'a> {
+    // Both the signal patterns and the outputs are glyphs on the display, so
+    // both constrain the wiring
+    fn new(display: &'a SegmentDisplay, patterns: &'a [Signals], outputs: &'a [Signals]) -> Self {
+        let all_segments = (1 << display.num_segments()) - 1;
+        let mut candidates = vec![all_segments; display.num_segments()];
+
+        // A pattern lights one of the glyphs with as many segments, so its wires
+        // lead to segments of those glyphs, and the segments all of them share
+        // must come from its wires
+        for &pattern in patterns.iter().chain(outputs) {
+            let length = pattern.count_ones();
+            let (union, intersection) = display
+                .glyphs
+                .iter()
+                .filter(|(_, glyph)| glyph.count_ones() == length)
+                .fold((0, all_segments), |(union, intersection), (_, glyph)| {
+                    (union | glyph, intersection & glyph)
+                });
+
+            for (wire, candidate) in candidates.iter_mut().enumerate() {
+                if pattern & 1 << wire != 0 {
+                    *candidate &= union;
+                } else {
+                    *candidate &= !intersection;
                 }
-                _ => (),
-            };
+            }
+        }
 
-            let completed_mapping = mapping
+        Self {
+            display,
+            patterns,
+            outputs,
+            candidates,
+            lit_wires: patterns
                 .iter()
-                .filter_map(|(_, values)| {
-                    if values.len() == 1 {
-                        Some(values[0])
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<char>>();
+                .chain(outputs)
+                .fold(0, |acc, pattern| acc | pattern),
+            solution: None,
+            is_ambiguous: false,
+        }
+    }
 
-            for possibility in completed_mapping {
-                for (_, possibilities) in mapping.iter_mut().filter(|(_, values)| values.len() != 1)
-                {
-                    let filtered_possibilities = possibilities
-                        .iter()
-                        .filter(|signal| &&possibility != signal).copied()
-                        .collect::<Vec<char>>();
+    fn is_valid(&self, wiring: &Wiring) -> bool {
+        self.patterns
+            .iter()
+            .chain(self.outputs)
+            .all(|&pattern| match wiring.apply(pattern) {
+                Some(segments) => self.display.symbol(segments).is_some(),
+                None => true,
+            })
+    }
+
+    // Assigns the most constrained wire first. Several wirings are fine as long
+    // as they all show the same outputs.
+    fn search(&mut self, wiring: &mut Wiring, used_segments: Signals) {
+        if self.is_ambiguous || !self.is_valid(wiring) {
+            return;
+        }
 
-                    *possibilities = filtered_possibilities;
+        let next_wire = (0..wiring.0.len())
+            .filter(|&wire| wiring.0[wire].is_none() && self.lit_wires & 1 << wire != 0)
+            .min_by_key(|&wire| (self.candidates[wire] & !used_segments).count_ones());
+
+        let wire = match next_wire {
+            Some(wire) => wire,
+            None => {
+                match &self.solution {
+                    Some(solution) => {
+                        self.is_ambiguous = self
+                            .outputs
+                            .iter()
+                            .any(|&output| solution.apply(output) != wiring.apply(output));
+                    }
+                    None => self.solution = Some(wiring.clone()),
                 }
+                return;
             }
-        }
-        let mut reverse_mapping = HashMap::new();
-
-        for (key, values) in &mapping {
-            for value in values {
-                reverse_mapping
-                    .entry(value)
-                    .and_modify(|e: &mut Vec<usize>| (*e).push(*key))
-                    .or_insert(vec![*key]);
+        };
+
+        for segment in 0..self.display.num_segments() {
+            if (self.candidates[wire] & !used_segments) & 1 << segment != 0 {
+                wiring.0[wire] = Some(segment);
+                self.search(wiring, used_segments | 1 << segment);
+                wiring.0[wire] = None;
             }
         }
+    }
 
-        for pattern in sorted_inputs {
-            if deduced_number_mapping.values().contains(&&pattern) {
-                continue;
-            }
+    fn solve(mut self) -> Result<Wiring, DecodeError> {
+        let mut wiring = Wiring(vec![None; self.display.num_segments()]);
+        self.search(&mut wiring, 0);
 
-            let signals = pattern.chars().collect::<Vec<char>>();
+        match (self.solution, self.is_ambiguous) {
+            (None, _) => Err(DecodeError::Inconsistent),
+            (Some(_), true) => Err(DecodeError::Ambiguous),
+            (Some(solution), false) => Ok(solution),
+        }
+    }
+}
 
-            let first_candidates = number_length_mapping[&pattern.len()]
-                .iter()
-                .filter_map(|candidate| {
-                    if !deduced_number_mapping.keys().contains(candidate) {
-                        let has_invalid_tiles =
-                            number_mapping[candidate].iter().fold(0, |acc, number| {
-                                if mapping[number].len() != 1 {
-                                    return acc;
-                                }
-
-                                acc + mapping[number].iter().fold(
-                                    0,
-                                    |inner_acc, required_character| {
-                                        inner_acc
-                                            + if !pattern.chars().contains(required_character) {
-                                                1
-                                            } else {
-                                                0
-                                            }
-                                    },
-                                )
-                            });
-                        if has_invalid_tiles == 0 {
-                            Some(*candidate)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<usize>>();
-
-            let mut required: Vec<usize> = vec![];
-            for signal in &signals {
-                let possibilities = reverse_mapping.get(&signal).unwrap();
-                match possibilities.len() {
-                    1 => required.extend(possibilities),
-                    2 => {
-                        let has_both = possibilities.iter().all(|&possibility| {
-                            mapping[&possibility]
-                                .iter()
-                                .all(|character| signals.contains(character))
-                        });
-
-                        if has_both {
-                            required.extend(possibilities);
-                        }
-                    }
-                    _ => println!("Something broken"),
-                };
-            }
-            required.sort_unstable();
-            required.dedup();
+// The decoded output, and the wiring that decodes it
+fn decode_entry(display: &SegmentDisplay, line: &str) -> Result<(String, Wiring), DecodeError> {
+    let (inputs, outputs) = line.split_once(" | ").unwrap();
+    let patterns = inputs
+        .split_whitespace()
+        .map(parse_signals)
+        .collect::<Vec<Signals>>();
+    let output_signals = outputs
+        .split_whitespace()
+        .map(parse_signals)
+        .collect::<Vec<Signals>>();
+
+    let wiring = WiringSolver::new(display, &patterns, &output_signals).solve()?;
+
+    let decoded = outputs
+        .split_whitespace()
+        .zip(output_signals)
+        .map(|(output, signals)| {
+            wiring
+                .apply(signals)
+                .and_then(|segments| display.symbol(segments))
+                .ok_or_else(|| DecodeError::UnknownOutput(output.to_string()))
+        })
+        .collect::<Result<String, DecodeError>>()?;
+
+    Ok((decoded, wiring))
+}
 
-            let mut candidates = vec![first_candidates
-                .iter()
-                .filter_map(|candidate| {
-                    if required
-                        .iter()
-                        .all(|x| number_mapping[candidate].contains(x))
-                    {
-                        Some(*candidate)
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<usize>>()];
-
-            for signal in &signals {
-                let possibilities = reverse_mapping.get(&signal).unwrap();
-
-                match possibilities.len() {
-                    1 => {
-                        let candidate = possibilities[0];
-                        for candidate_list in &mut candidates {
-                            *candidate_list = candidate_list
-                                .iter()
-                                .filter(|e| reverse_number_mapping[&candidate].contains(e)).copied()
-                                .collect();
-                        }
-                    }
-                    2 => {
-                        let has_both = possibilities.iter().all(|&possibility| {
-                            mapping[&possibility]
-                                .iter()
-                                .all(|character| signals.contains(character))
-                        });
-
-                        if has_both {
-                            for candidate_list in &mut candidates {
-                                for candidate in possibilities {
-                                    let filtered_list = candidate_list
-                                        .iter()
-                                        .filter_map(|e| {
-                                            if reverse_number_mapping[&candidate].contains(e) {
-                                                Some(*e)
-                                            } else {
-                                                None
-                                            }
-                                        })
-                                        .collect::<Vec<usize>>();
-                                    if !filtered_list.is_empty() {
-                                        *candidate_list = filtered_list;
-                                    }
-                                }
-                            }
-                        } else {
-                            let mut candidates_list = [candidates.clone(), candidates.clone()];
-
-                            for (index, candidate) in possibilities.iter().enumerate() {
-                                for candidate_list in &mut candidates_list[index] {
-                                    let filtered_list = candidate_list
-                                        .iter()
-                                        .filter_map(|e| {
-                                            if reverse_number_mapping[&candidate].contains(e) {
-                                                Some(*e)
-                                            } else {
-                                                None
-                                            }
-                                        })
-                                        .collect::<Vec<usize>>();
-                                    if !filtered_list.is_empty() {
-                                        *candidate_list = filtered_list;
-                                    }
-                                }
-                            }
-                            candidates = candidates_list[0].clone();
-                            candidates.extend(candidates_list[1].clone());
-                        }
-                    }
-                    _ => println!("Something broken"),
-                }
-            }
-            candidates = candidates.clone().into_iter().unique().collect();
-            if candidates.len() == 1 && candidates[0].len() == 1 {
-                let single_match = candidates[0][0];
-                deduced_number_mapping.insert(single_match, pattern);
+fn solve_part1(inputfile: String, display: &SegmentDisplay) -> usize {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+    let unique_lengths = display.unique_lengths();
+
+    contents
+        .lines()
+        .flat_map(|line| line.split(" | ").nth(1).unwrap().split_whitespace())
+        .filter(|output| unique_lengths.contains(&(output.len() as u32)))
+        .count()
+}
+
+// Sums the entries that decode to a number
+fn solve_part2(inputfile: String, display: &SegmentDisplay) -> usize {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| match decode_entry(display, line) {
+            Ok((decoded, _)) => decoded.parse::<usize>().ok(),
+            Err(error) => {
+                println!("line {}: {:?}", index + 1, error);
+                None
             }
-        }
+        })
+        .sum()
+}
 
-        let reversed_deduced = deduced_number_mapping
-            .iter()
-            .map(|(key, value)| (value.clone(), *key))
-            .collect::<HashMap<String, usize>>();
-        acc + outputs
-            .iter()
-            .map(|raw| char::from_digit(reversed_deduced[raw] as u32, 10).unwrap())
-            .join("")
-            .parse::<usize>()
-            .unwrap()
-    })
+fn print_decoded_entries(inputfile: String, display: &SegmentDisplay) {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+
+    for line in contents.lines() {
+        match decode_entry(display, line) {
+            Ok((decoded, wiring)) => println!("{}    ({})", decoded, wiring.describe(display)),
+            Err(error) => println!("{:?}", error),
+        }
+    }
 }
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    println!("Part1: {}", solve_part1(args[1].to_string()));
-    println!("Part2: {}", solve_part2(args[1].to_string()));
+
+    // An optional display definition replaces the seven segment digits
+    let display = match args.get(2) {
+        Some(definitionfile) => SegmentDisplay::from_file(definitionfile),
+        None => SegmentDisplay::seven_segment(),
+    };
+
+    println!("Part1: {}", solve_part1(args[1].to_string(), &display));
+    println!("Part2: {}", solve_part2(args[1].to_string(), &display));
+
+    if args.get(2).is_some() {
+        print_decoded_entries(args[1].to_string(), &display);
+    }
 }
@@ -1,8 +1,47 @@
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::env;
+use std::hash::Hash;
 
 type Coordinate = (i32, i32);
+type Voxel = (i32, i32, i32);
+
+// A location on a height map of any dimension
+trait Location: Copy + Eq + Hash + std::fmt::Debug {
+    fn neighbours(&self) -> Vec<Self>;
+    // Sort key that orders locations by slice, row, then column
+    fn reading_order(&self) -> (i32, i32, i32);
+}
+
+impl Location for Coordinate {
+    fn neighbours(&self) -> Vec<Self> {
+        AdjacentRange::new(*self).collect()
+    }
+
+    fn reading_order(&self) -> (i32, i32, i32) {
+        (0, self.1, self.0)
+    }
+}
+
+impl Location for Voxel {
+    fn neighbours(&self) -> Vec<Self> {
+        [
+            (-1, 0, 0),
+            (1, 0, 0),
+            (0, -1, 0),
+            (0, 1, 0),
+            (0, 0, -1),
+            (0, 0, 1),
+        ]
+        .iter()
+        .map(|offset| (self.0 + offset.0, self.1 + offset.1, self.2 + offset.2))
+        .collect()
+    }
+
+    fn reading_order(&self) -> (i32, i32, i32) {
+        (self.2, self.1, self.0)
+    }
+}
 
 struct AdjacentRange {
     start: Coordinate,
@@ -53,79 +92,240 @@ fn parse_input(inputfile: String) -> HashMap<Coordinate, u32> {
     height_map
 }
 
-fn solve_part1(inputfile: String) -> usize {
-    let height_map = parse_input(inputfile);
-
-    height_map.iter().fold(0, |acc, (position, height)| {
-        let is_lowest =
-            AdjacentRange::new(*position).all(|neighbour| match height_map.get(&neighbour) {
-                Some(neighbour_height) => neighbour_height > height,
-                None => true,
-            });
+// A volume is a stack of 2D slices separated by empty lines
+fn parse_volume(inputfile: String) -> HashMap<Voxel, u32> {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
 
-        acc + (if is_lowest { height + 1 } else { 0 }) as usize
-    })
+    let mut volume = HashMap::new();
+
+    contents
+        .split("\n\n")
+        .filter(|slice| !slice.trim().is_empty())
+        .enumerate()
+        .for_each(|(z, slice)| {
+            slice.lines().enumerate().for_each(|(y, line)| {
+                line.chars().enumerate().for_each(|(x, c)| {
+                    let position: Voxel = (x as i32, y as i32, z as i32);
+                    volume.entry(position).or_insert(c.to_digit(10).unwrap());
+                })
+            })
+        });
+    volume
 }
 
-fn solve_part2(inputfile: String) -> usize {
-    let height_map = parse_input(inputfile);
-
-    let lowest_points = height_map
+fn low_points<L: Location>(height_map: &HashMap<L, u32>) -> Vec<L> {
+    height_map
         .iter()
-        .filter_map(|(position, height)| {
-            let is_lowest =
-                AdjacentRange::new(*position).all(|neighbour| match height_map.get(&neighbour) {
+        .filter(|(position, height)| {
+            position
+                .neighbours()
+                .iter()
+                .all(|neighbour| match height_map.get(neighbour) {
                     Some(neighbour_height) => neighbour_height > height,
                     None => true,
-                });
-
-            if is_lowest {
-                Some((*position, *height))
-            } else {
-                None
-            }
+                })
         })
-        .collect::<Vec<(Coordinate, u32)>>();
+        .map(|(position, _)| *position)
+        .sorted_by_key(|position| position.reading_order())
+        .collect()
+}
 
-    lowest_points
+fn risk_level<L: Location>(height_map: &HashMap<L, u32>) -> usize {
+    low_points(height_map)
         .iter()
-        .map(|(lowest_point, height)| {
-            let mut queue = vec![*lowest_point];
-            let mut visited: Vec<Coordinate> = vec![];
-
-            while !queue.is_empty() {
-                match queue.pop() {
-                    Some(position) => {
-                        if !visited.contains(&position) {
-                            visited.push(position);
-
-                            for neighbour in AdjacentRange::new(position) {
-                                match height_map.get(&neighbour) {
-                                    Some(neighbour_height) => {
-                                        if *neighbour_height > *height
-                                            && *neighbour_height != 9
-                                            && !visited.contains(&neighbour)
-                                        {
-                                            queue.push(neighbour);
-                                        }
-                                    }
-                                    None => (),
-                                }
-                            }
-                        }
-                    }
-                    None => (),
-                }
-            }
+        .map(|position| height_map[position] as usize + 1)
+        .sum()
+}
 
-            visited.len()
-        })
+fn largest_basins_product<L: Location>(height_map: &HashMap<L, u32>) -> usize {
+    segment_basins(height_map)
+        .basins
+        .iter()
+        .map(|basin| basin.size)
         .sorted()
         .rev()
         .take(3)
         .product()
 }
 
+fn solve_part1(inputfile: String) -> usize {
+    let height_map = parse_input(inputfile);
+
+    risk_level(&height_map)
+}
+
+struct UnionFind {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        Self {
+            parents: (0..size).collect(),
+            sizes: vec![1; size],
+        }
+    }
+
+    fn find(&mut self, mut index: usize) -> usize {
+        while self.parents[index] != index {
+            self.parents[index] = self.parents[self.parents[index]];
+            index = self.parents[index];
+        }
+        index
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.sizes[a] < self.sizes[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parents[b] = a;
+        self.sizes[a] += self.sizes[b];
+    }
+}
+
+#[derive(Debug)]
+struct Basin<L: Location> {
+    // Number of locations, the volume for 3D height maps
+    size: usize,
+    // The lowest location, the first one in reading order if the bottom is flat
+    low_point: L,
+    low_height: u32,
+    // Locations next to a ridge (height 9) or the edge of the map
+    boundary: Vec<L>,
+}
+
+#[derive(Debug)]
+struct BasinMap<L: Location> {
+    // Basin index for every location that isn't a ridge
+    labels: HashMap<L, usize>,
+    basins: Vec<Basin<L>>,
+}
+
+// Every location lower than 9 belongs to the basin of all the locations it's
+// connected to without crossing a ridge
+fn segment_basins<L: Location>(height_map: &HashMap<L, u32>) -> BasinMap<L> {
+    let positions = height_map
+        .iter()
+        .filter(|(_, &height)| height != 9)
+        .map(|(position, _)| *position)
+        .sorted_by_key(|position| position.reading_order())
+        .collect::<Vec<L>>();
+    let indices = positions
+        .iter()
+        .enumerate()
+        .map(|(index, position)| (*position, index))
+        .collect::<HashMap<L, usize>>();
+
+    let mut union_find = UnionFind::new(positions.len());
+    for (index, position) in positions.iter().enumerate() {
+        for neighbour in position.neighbours() {
+            if let Some(&neighbour_index) = indices.get(&neighbour) {
+                union_find.union(index, neighbour_index);
+            }
+        }
+    }
+
+    let mut labels = HashMap::new();
+    let mut basins: Vec<Basin<L>> = vec![];
+    let mut basin_ids = HashMap::new();
+
+    for (index, position) in positions.iter().enumerate() {
+        let root = union_find.find(index);
+        let next_id = basin_ids.len();
+        let id = *basin_ids.entry(root).or_insert(next_id);
+        labels.insert(*position, id);
+
+        let height = height_map[position];
+        if id == basins.len() {
+            basins.push(Basin {
+                size: 0,
+                low_point: *position,
+                low_height: height,
+                boundary: vec![],
+            });
+        }
+
+        let basin = &mut basins[id];
+        basin.size += 1;
+        if height < basin.low_height {
+            basin.low_point = *position;
+            basin.low_height = height;
+        }
+        if position
+            .neighbours()
+            .iter()
+            .any(|neighbour| !indices.contains_key(neighbour))
+        {
+            basin.boundary.push(*position);
+        }
+    }
+
+    BasinMap { labels, basins }
+}
+
+fn solve_part2(inputfile: String) -> usize {
+    let height_map = parse_input(inputfile);
+
+    largest_basins_product(&height_map)
+}
+
+// Lists every basin, followed by the map with each location labeled by its basin
+fn print_basins(inputfile: String) {
+    let height_map = parse_input(inputfile);
+    let basin_map = segment_basins(&height_map);
+
+    for (id, basin) in basin_map.basins.iter().enumerate() {
+        println!(
+            "basin {}: size {}, low point {:?} (height {}), {} boundary locations",
+            id,
+            basin.size,
+            basin.low_point,
+            basin.low_height,
+            basin.boundary.len()
+        );
+    }
+
+    let width = height_map.keys().map(|pos| pos.0).max().unwrap_or(-1) + 1;
+    let height = height_map.keys().map(|pos| pos.1).max().unwrap_or(-1) + 1;
+    for y in 0..height {
+        let row = (0..width)
+            .map(|x| match basin_map.labels.get(&(x, y)) {
+                Some(id) => std::char::from_digit((id % 36) as u32, 36).unwrap(),
+                None => '.',
+            })
+            .collect::<String>();
+        println!("{}", row);
+    }
+}
+
+fn print_volume_analysis(inputfile: String) {
+    let volume = parse_volume(inputfile);
+    let basin_map = segment_basins(&volume);
+
+    println!("Part1: {}", risk_level(&volume));
+    println!("Part2: {}", largest_basins_product(&volume));
+
+    for position in low_points(&volume) {
+        println!("low point {:?} (height {})", position, volume[&position]);
+    }
+    for (id, basin) in basin_map.basins.iter().enumerate() {
+        println!(
+            "basin {}: volume {}, low point {:?} (height {}), {} boundary voxels",
+            id,
+            basin.size,
+            basin.low_point,
+            basin.low_height,
+            basin.boundary.len()
+        );
+    }
+}
+
 use image::ImageBuffer;
 type Color = (u8, u8, u8);
 
@@ -154,19 +354,69 @@ fn draw_pixel(pixels: &mut Vec<(Coordinate, Color)>, position: Coordinate, color
     pixels.push((position, color));
 }
 
-fn draw_height_map(inputfile: String) {
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MapStyle {
+    Height,
+    Basins,
+}
+
+impl MapStyle {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "height" => Some(MapStyle::Height),
+            "basins" => Some(MapStyle::Basins),
+            _ => None,
+        }
+    }
+}
+
+const RIDGE_COLOR: Color = (32, 32, 32);
+const LOW_POINT_COLOR: Color = (230, 30, 30);
+
+fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> Color {
+    let sector = (hue * 6.0).floor();
+    let fraction = hue * 6.0 - sector;
+    let p = value * (1.0 - saturation);
+    let q = value * (1.0 - fraction * saturation);
+    let t = value * (1.0 - (1.0 - fraction) * saturation);
+
+    let (r, g, b) = match sector as i32 % 6 {
+        0 => (value, t, p),
+        1 => (q, value, p),
+        2 => (p, value, t),
+        3 => (p, q, value),
+        4 => (t, p, value),
+        _ => (value, p, q),
+    };
+    ((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8)
+}
+
+// Neighbouring basin ids get hues far apart, the largest basins are vivid and
+// the rest are pale. Deeper locations are drawn darker.
+fn basin_color(id: usize, is_largest: bool, height: u32) -> Color {
+    let hue = (id as f64 * 0.618_034).fract();
+    let saturation = if is_largest { 0.9 } else { 0.3 };
+    let value = 1.0 - (8 - height.min(8)) as f64 * 0.05;
+
+    hsv_to_rgb(hue, saturation, value)
+}
+
+fn draw_height_map(inputfile: String, style: MapStyle, scale: u32) {
     let height_map = parse_input(inputfile);
+    let basin_map = segment_basins(&height_map);
+
+    let largest_basins = (0..basin_map.basins.len())
+        .sorted_by_key(|&id| std::cmp::Reverse(basin_map.basins[id].size))
+        .take(3)
+        .collect::<Vec<usize>>();
 
-    let x_min = height_map.iter().map(|(pos, _)| pos.0).min().unwrap();
-    let x_max = height_map.iter().map(|(pos, _)| pos.0).max().unwrap();
-    let y_min = height_map.iter().map(|(pos, _)| pos.1).min().unwrap();
-    let y_max = height_map.iter().map(|(pos, _)| pos.1).max().unwrap();
-    let x_range = (x_max - x_min) as u32;
-    let y_range = (y_max - y_min) as u32;
-    let dimensions: Coordinate = (1 + x_range as i32, 1 + y_range as i32);
+    let x_min = height_map.keys().map(|pos| pos.0).min().unwrap();
+    let x_max = height_map.keys().map(|pos| pos.0).max().unwrap();
+    let y_min = height_map.keys().map(|pos| pos.1).min().unwrap();
+    let y_max = height_map.keys().map(|pos| pos.1).max().unwrap();
+    let dimensions: Coordinate = (1 + x_max - x_min, 1 + y_max - y_min);
 
     let border = 2;
-    let scale = 2;
     let real_size = (
         (scale * (dimensions.0 + border * 2) as u32),
         (scale * (dimensions.1 + border * 2) as u32),
@@ -174,20 +424,40 @@ fn draw_height_map(inputfile: String) {
 
     let mut pixels = Vec::<(Coordinate, Color)>::new();
 
-    for y in 0..y_range {
-        for x in 0..x_range {
-            let block_pos = (border + x as i32, border + y as i32);
-            let pos = (x as i32, y as i32);
+    for y in y_min..=y_max {
+        for x in x_min..=x_max {
+            let block_pos = (border + x - x_min, border + y - y_min);
+            let pos = (x, y);
 
-            match height_map.get(&pos) {
-                Some(&height) => draw_pixel(&mut pixels, block_pos, height as usize),
+            let height = match height_map.get(&pos) {
+                Some(&height) => height,
                 None => {
                     println!("Didn't find {:?}", pos);
+                    continue;
+                }
+            };
+
+            match (style, basin_map.labels.get(&pos)) {
+                (MapStyle::Height, _) => draw_pixel(&mut pixels, block_pos, height as usize),
+                (MapStyle::Basins, None) => pixels.push((block_pos, RIDGE_COLOR)),
+                (MapStyle::Basins, Some(&id)) => {
+                    let is_largest = largest_basins.contains(&id);
+                    pixels.push((block_pos, basin_color(id, is_largest, height)));
                 }
             }
         }
     }
 
+    if style == MapStyle::Basins {
+        for basin in &basin_map.basins {
+            let block_pos = (
+                border + basin.low_point.0 - x_min,
+                border + basin.low_point.1 - y_min,
+            );
+            pixels.push((block_pos, LOW_POINT_COLOR));
+        }
+    }
+
     let mut img = ImageBuffer::from_fn(real_size.0, real_size.1, |_x, _y| {
         image::Rgb([255, 255, 255])
     });
@@ -195,20 +465,48 @@ fn draw_height_map(inputfile: String) {
     for ((x, y), color) in pixels {
         let pixel = image::Rgb([color.0, color.1, color.2]);
         if x >= 0 && y >= 0 && x < real_size.0 as i32 && y < real_size.1 as i32 {
-            img.put_pixel(scale * x as u32 + 0, scale * y as u32 + 0, pixel);
-            img.put_pixel(scale * x as u32 + 1, scale * y as u32 + 0, pixel);
-            img.put_pixel(scale * x as u32 + 0, scale * y as u32 + 1, pixel);
-            img.put_pixel(scale * x as u32 + 1, scale * y as u32 + 1, pixel);
+            for offset_y in 0..scale {
+                for offset_x in 0..scale {
+                    img.put_pixel(
+                        scale * x as u32 + offset_x,
+                        scale * y as u32 + offset_y,
+                        pixel,
+                    );
+                }
+            }
         }
     }
 
-    img.save("frames/day09.png".to_string()).unwrap();
+    img.save("frames/day09.png").unwrap();
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+
+    // The input is a stack of 2D slices, analysed with 6-neighbour connectivity
+    if args.iter().any(|arg| arg == "--volume") {
+        print_volume_analysis(args[1].to_string());
+        return;
+    }
+
     println!("Part1: {}", solve_part1(args[1].to_string()));
     println!("Part2: {}", solve_part2(args[1].to_string()));
 
-    draw_height_map(args[1].to_string());
+    if args.iter().any(|arg| arg == "--basins") {
+        print_basins(args[1].to_string());
+    }
+
+    // e.g. "--draw=basins --scale=4"
+    let style = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--draw="))
+        .map_or(MapStyle::Height, |name| {
+            MapStyle::parse(name).expect("Unknown map style")
+        });
+    let scale = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--scale="))
+        .map_or(2, |factor| factor.parse::<u32>().expect("Invalid scale"));
+
+    draw_height_map(args[1].to_string(), style, scale);
 }
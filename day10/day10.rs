@@ -1,126 +1,489 @@
 use itertools::Itertools;
+use num_bigint::BigUint;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::env;
 
-fn get_closing_counterpart(open: char) -> char {
-    let open_lut = vec!['[', '(', '{', '<'];
-    let close_lut = vec![']', ')', '}', '>'];
+// Columns are character indices into the line, counted from 0
+#[derive(Debug, Clone, PartialEq)]
+struct OpenChunk {
+    close: char,
+    column: usize,
+}
 
-    let (open_index, _) = open_lut
-        .iter()
-        .enumerate()
-        .find(|(_index, x)| **x == open)
-        .unwrap();
+#[derive(Debug, Clone, PartialEq)]
+enum LineStatus {
+    Valid,
+    // The first illegal character, and the chunk it should have closed
+    // (None if nothing was open)
+    Corrupted {
+        column: usize,
+        found: char,
+        expected: Option<OpenChunk>,
+    },
+    // The characters that would close everything still open
+    Incomplete {
+        completion: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Edit {
+    Replace { column: usize, from: char, to: char },
+    Delete { column: usize, ch: char },
+    Insert { column: usize, ch: char },
+    Append(String),
+}
 
-    *close_lut.get(open_index).unwrap()
+#[derive(Debug, Clone)]
+struct SyntaxChecker {
+    // Opening to closing character, and the other way around
+    closers: HashMap<char, char>,
+    openers: HashMap<char, char>,
+    // Brackets inside strings and after a line comment aren't checked
+    strings: HashMap<char, char>,
+    line_comment: Option<char>,
+    escape: char,
+    // Any character that isn't a delimiter is illegal
+    strict: bool,
 }
 
-fn is_counterpart(open: char, close: char) -> bool {
-    let open_lut = vec!['[', '(', '{', '<'];
-    let close_lut = vec![']', ')', '}', '>'];
+impl SyntaxChecker {
+    fn new(pairs: &[(char, char)]) -> Self {
+        for &(open, close) in pairs {
+            if open == close {
+                panic!("Delimiter pair {}{} can't be nested", open, close);
+            }
+        }
 
-    match close_lut.iter().enumerate().find(|(_index, x)| **x == close) {
-        Some((close_index, _)) => match open_lut.get(close_index) {
-            Some(open_ch) => *open_ch == open,
-            None => false,
-        },
-        None => false,
+        Self {
+            closers: pairs.iter().copied().collect(),
+            openers: pairs.iter().map(|&(open, close)| (close, open)).collect(),
+            strings: HashMap::new(),
+            line_comment: None,
+            escape: '\\',
+            strict: false,
+        }
     }
-}
 
-fn solve_part1(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+    // The chunk delimiters of the navigation subsystem
+    fn navigation() -> Self {
+        Self {
+            strict: true,
+            ..Self::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')])
+        }
+    }
+
+    fn with_strings(mut self, delimiters: &[(char, char)]) -> Self {
+        self.strings = delimiters.iter().copied().collect();
+        self
+    }
 
-    let mut syntax_error_score = 0;
+    fn with_line_comment(mut self, start: char) -> Self {
+        self.line_comment = Some(start);
+        self
+    }
+
+    // Splits a list of delimiters like "()[]{}" into pairs
+    fn parse_pairs(spec: &str) -> Vec<(char, char)> {
+        spec.chars().tuples().collect::<Vec<(char, char)>>()
+    }
+
+    fn check(&self, line: &str) -> LineStatus {
+        let mut stack: Vec<OpenChunk> = vec![];
+        let mut string_end: Option<char> = None;
+        let mut chars = line.chars().enumerate();
 
-    let _positions = contents.lines().for_each(|line| {
-        let mut stack: Vec<char> = vec![];
-        for ch in line.chars() {
-            match ch {
-                '[' | '(' | '{' | '<' => {
-                    stack.push(ch);
+        while let Some((column, ch)) = chars.next() {
+            if let Some(end) = string_end {
+                if ch == self.escape {
+                    chars.next();
+                } else if ch == end {
+                    string_end = None;
                 }
-                ']' | ')' | '}' | '>' => match stack.pop() {
-                    Some(matching) => {
-                        if is_counterpart(matching, ch) {
-                            //println!("popping {}", ch);
-                        } else {
-                            syntax_error_score += match ch {
-                                ']' => 57,
-                                ')' => 3,
-                                '}' => 1197,
-                                '>' => 25137,
-                                _ => 0,
-                            };
+                continue;
+            }
+
+            if Some(ch) == self.line_comment {
+                break;
+            } else if let Some(&end) = self.strings.get(&ch) {
+                string_end = Some(end);
+            } else if let Some(&close) = self.closers.get(&ch) {
+                stack.push(OpenChunk { close, column });
+            } else if self.openers.contains_key(&ch) || self.strict {
+                match stack.pop() {
+                    Some(expected) if expected.close == ch => {}
+                    expected => {
+                        return LineStatus::Corrupted {
+                            column,
+                            found: ch,
+                            expected,
                         }
                     }
-                    None => {
-                        println!("missing {}", ch);
-                    }
-                },
-                _ => {
-                    println!("unknown character: {}", ch);
                 }
             }
         }
-    });
-    syntax_error_score
-}
 
-fn solve_part2(inputfile: String) -> usize {
-    let contents =
-        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+        let completion = string_end
+            .into_iter()
+            .chain(stack.iter().rev().map(|chunk| chunk.close))
+            .collect::<String>();
+
+        if completion.is_empty() {
+            LineStatus::Valid
+        } else {
+            LineStatus::Incomplete { completion }
+        }
+    }
+
+    // Fewest edits that fix the line, searched breadth first: the first illegal
+    // character is deleted, replaced by the expected closer or has the expected
+    // closer inserted before it, and completing an incomplete line is one more
+    // edit. Characters before the first illegal one are never edited. Every
+    // character keeps the column it had in the line, inserted ones that of the
+    // character they were inserted before, so edits are reported in those columns.
+    fn repair(&self, line: &str) -> (String, Vec<Edit>) {
+        let line = line.chars().enumerate().map(|(column, ch)| (ch, column));
+        let mut queue = VecDeque::from([(line.collect::<Vec<(char, usize)>>(), vec![])]);
+        let mut seen = HashSet::new();
+        let mut completed: Option<(String, Vec<Edit>)> = None;
+
+        while let Some((chars, edits)) = queue.pop_front() {
+            if let Some((_, completed_edits)) = &completed {
+                if edits.len() >= completed_edits.len() {
+                    break;
+                }
+            }
 
-    let _completion_error_score = 0;
-
-    let completion_error_scores = contents
-        .lines()
-        .filter_map(|line| {
-            let mut stack: Vec<char> = vec![];
-            let mut is_corrupted = false;
-            for ch in line.chars() {
-                match ch {
-                    '[' | '(' | '{' | '<' => {
-                        stack.push(ch);
+            let repaired = chars.iter().map(|&(ch, _)| ch).collect::<String>();
+            match self.check(&repaired) {
+                LineStatus::Valid => return (repaired, edits),
+                LineStatus::Incomplete { completion } => {
+                    if completed.is_none() {
+                        let mut edits = edits;
+                        edits.push(Edit::Append(completion.clone()));
+                        completed = Some((repaired + &completion, edits));
                     }
-                    ']' | ')' | '}' | '>' => match stack.pop() {
-                        Some(matching) => {
-                            if !is_counterpart(matching, ch) {
-                                is_corrupted = true
-                            }
-                        }
-                        None => {
-                            println!("missing {}", ch);
+                }
+                LineStatus::Corrupted {
+                    column,
+                    found,
+                    expected,
+                } => {
+                    let line_column = chars[column].1;
+                    let mut candidates = vec![];
+
+                    let mut deleted = chars.clone();
+                    deleted.remove(column);
+                    candidates.push((
+                        deleted,
+                        Edit::Delete {
+                            column: line_column,
+                            ch: found,
+                        },
+                    ));
+
+                    if let Some(expected) = expected {
+                        let mut replaced = chars.clone();
+                        replaced[column].0 = expected.close;
+                        candidates.push((
+                            replaced,
+                            Edit::Replace {
+                                column: line_column,
+                                from: found,
+                                to: expected.close,
+                            },
+                        ));
+
+                        let mut inserted = chars.clone();
+                        inserted.insert(column, (expected.close, line_column));
+                        candidates.push((
+                            inserted,
+                            Edit::Insert {
+                                column: line_column,
+                                ch: expected.close,
+                            },
+                        ));
+                    }
+
+                    for (candidate, edit) in candidates {
+                        if seen.insert(candidate.iter().map(|&(ch, _)| ch).collect::<String>()) {
+                            let mut edits = edits.clone();
+                            edits.push(edit);
+                            queue.push_back((candidate, edits));
                         }
-                    },
-                    _ => {
-                        println!("unknown character: {}", ch);
                     }
                 }
             }
+        }
 
-            if !is_corrupted {
-                Some(stack.iter().rev().fold(0, |acc, open_ch| {
-                    5 * acc
-                        + match get_closing_counterpart(*open_ch) {
-                            ')' => 1,
-                            ']' => 2,
-                            '}' => 3,
-                            '>' => 4,
-                            _ => 0,
-                        }
-                }))
-            } else {
-                None
+        // Deleting every illegal character always ends the search
+        completed.unwrap()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ScoreTable {
+    // Points for the first illegal character of a corrupted line
+    syntax_error: HashMap<char, usize>,
+    // Points per closing character, the completion score is a number in this base
+    completion: HashMap<char, u32>,
+    base: u32,
+}
+
+impl Default for ScoreTable {
+    fn default() -> Self {
+        Self {
+            syntax_error: [(')', 3), (']', 57), ('}', 1197), ('>', 25137)].into(),
+            completion: [(')', 1), (']', 2), ('}', 3), ('>', 4)].into(),
+            base: 5,
+        }
+    }
+}
+
+impl ScoreTable {
+    // A "base <n>" line, then one "<closer> <syntax error points> <completion points>"
+    // line per character. Lines starting with # are comments.
+    fn from_file(scorefile: &str) -> Self {
+        let contents =
+            std::fs::read_to_string(scorefile).expect("Something went wrong reading the scores");
+
+        let mut table = Self {
+            syntax_error: HashMap::new(),
+            completion: HashMap::new(),
+            base: 5,
+        };
+
+        for line in contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let tokens = line.split_whitespace().collect::<Vec<&str>>();
+            match tokens[..] {
+                ["base", base] => table.base = base.parse().expect("Invalid base"),
+                [closer, syntax_error, completion] => {
+                    let closer = closer.chars().next().unwrap();
+                    table
+                        .syntax_error
+                        .insert(closer, syntax_error.parse().expect("Invalid score"));
+                    table
+                        .completion
+                        .insert(closer, completion.parse().expect("Invalid score"));
+                }
+                _ => panic!("Invalid score line: {}", line),
             }
+        }
+        table
+    }
+
+    fn syntax_error_score(&self, illegal: char) -> usize {
+        *self.syntax_error.get(&illegal).unwrap_or(&0)
+    }
+
+    // Completion scores grow exponentially with the length of the completion
+    fn completion_score(&self, completion: &str) -> BigUint {
+        completion.chars().fold(BigUint::from(0u32), |acc, close| {
+            acc * self.base + *self.completion.get(&close).unwrap_or(&0)
+        })
+    }
+}
+
+// Nearest-rank percentile of sorted scores
+fn percentile(sorted_scores: &[BigUint], percent: usize) -> &BigUint {
+    let rank = (percent * sorted_scores.len()).div_ceil(100).max(1);
+    &sorted_scores[rank - 1]
+}
+
+fn check_lines(inputfile: String, checker: &SyntaxChecker) -> Vec<LineStatus> {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+
+    contents.lines().map(|line| checker.check(line)).collect()
+}
+
+fn completion_scores(statuses: &[LineStatus], scores: &ScoreTable) -> Vec<BigUint> {
+    statuses
+        .iter()
+        .filter_map(|status| match status {
+            LineStatus::Incomplete { completion } => Some(scores.completion_score(completion)),
+            _ => None,
         })
         .sorted()
-        .collect::<Vec<usize>>();
+        .collect()
+}
+
+fn solve_part1(inputfile: String, scores: &ScoreTable) -> usize {
+    check_lines(inputfile, &SyntaxChecker::navigation())
+        .iter()
+        .map(|status| match status {
+            LineStatus::Corrupted { found, .. } => scores.syntax_error_score(*found),
+            _ => 0,
+        })
+        .sum()
+}
 
-    completion_error_scores[completion_error_scores.len() / 2]
+// None if no line is incomplete
+fn solve_part2(inputfile: String, scores: &ScoreTable) -> Option<BigUint> {
+    let statuses = check_lines(inputfile, &SyntaxChecker::navigation());
+    let completion_scores = completion_scores(&statuses, scores);
+
+    if completion_scores.is_empty() {
+        None
+    } else {
+        Some(percentile(&completion_scores, 50).clone())
+    }
+}
+
+fn print_statistics(inputfile: String, checker: &SyntaxChecker, scores: &ScoreTable) {
+    let statuses = check_lines(inputfile, checker);
+
+    let mut illegal_characters = BTreeMap::<char, usize>::new();
+    for status in &statuses {
+        if let LineStatus::Corrupted { found, .. } = status {
+            *illegal_characters.entry(*found).or_default() += 1;
+        }
+    }
+    for (illegal, count) in illegal_characters {
+        println!(
+            "{} illegal {} lines, {} points",
+            count,
+            illegal,
+            count * scores.syntax_error_score(illegal)
+        );
+    }
+
+    let completion_scores = completion_scores(&statuses, scores);
+    if completion_scores.is_empty() {
+        return;
+    }
+
+    println!("{} incomplete lines", completion_scores.len());
+    for percent in [0, 10, 25, 50, 75, 90, 100] {
+        println!(
+            "  p{:<3} {}",
+            percent,
+            percentile(&completion_scores, percent)
+        );
+    }
+
+    // Number of incomplete lines by the number of digits of their score
+    println!("completion score distribution:");
+    for (digits, group) in &completion_scores
+        .iter()
+        .group_by(|score| score.to_string().len())
+    {
+        let count = group.count();
+        println!("  {:>3} digits: {:>4} {}", digits, count, "#".repeat(count));
+    }
 }
+
+// Columns are printed counted from 1
+fn print_diagnostics(inputfile: String, checker: &SyntaxChecker) {
+    for (index, status) in check_lines(inputfile, checker).iter().enumerate() {
+        match status {
+            LineStatus::Valid => println!("{:>5}: valid", index + 1),
+            LineStatus::Corrupted {
+                column,
+                found,
+                expected,
+            } => match expected {
+                Some(expected) => println!(
+                    "{:>5}:{}: corrupted, expected {} to close column {} but found {}",
+                    index + 1,
+                    column + 1,
+                    expected.close,
+                    expected.column + 1,
+                    found
+                ),
+                None => println!(
+                    "{:>5}:{}: corrupted, unexpected {}",
+                    index + 1,
+                    column + 1,
+                    found
+                ),
+            },
+            LineStatus::Incomplete { completion } => {
+                println!("{:>5}: incomplete, missing {}", index + 1, completion)
+            }
+        }
+    }
+}
+
+fn print_repairs(inputfile: String, checker: &SyntaxChecker) {
+    let contents =
+        std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
+
+    for line in contents.lines() {
+        let (repaired, edits) = checker.repair(line);
+        let edits = edits
+            .iter()
+            .map(|edit| match edit {
+                Edit::Replace { column, from, to } => {
+                    format!("replace {} with {} at column {}", from, to, column + 1)
+                }
+                Edit::Delete { column, ch } => format!("delete {} at column {}", ch, column + 1),
+                Edit::Insert { column, ch } => {
+                    format!("insert {} before column {}", ch, column + 1)
+                }
+                Edit::Append(completion) => format!("append {}", completion),
+            })
+            .join(", ");
+
+        if edits.is_empty() {
+            println!("{}", repaired);
+        } else {
+            println!("{}    ({})", repaired, edits);
+        }
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    println!("Part1: {}", solve_part1(args[1].to_string()));
-    println!("Part2: {}", solve_part2(args[1].to_string()));
+
+    // Lint with other delimiters, e.g. "--pairs=()[]{} --strings=\"\" --comment=#"
+    let option = |name: &str| {
+        args.iter()
+            .find_map(|arg| arg.strip_prefix(&format!("--{}=", name)))
+    };
+    let has_flag = |name: &str| args.iter().any(|arg| *arg == format!("--{}", name));
+
+    // e.g. "--scores=day10/scores.txt"
+    let scores = match option("scores") {
+        Some(scorefile) => ScoreTable::from_file(scorefile),
+        None => ScoreTable::default(),
+    };
+
+    let diagnostics = has_flag("stats") || has_flag("fix") || has_flag("diagnose");
+
+    let checker = match option("pairs") {
+        Some(pairs) => {
+            let mut checker = SyntaxChecker::new(&SyntaxChecker::parse_pairs(pairs));
+            if let Some(strings) = option("strings") {
+                checker = checker.with_strings(&SyntaxChecker::parse_pairs(strings));
+            }
+            if let Some(comment) = option("comment").and_then(|comment| comment.chars().next()) {
+                checker = checker.with_line_comment(comment);
+            }
+            checker
+        }
+        None if diagnostics => SyntaxChecker::navigation(),
+        None => {
+            println!("Part1: {}", solve_part1(args[1].to_string(), &scores));
+            match solve_part2(args[1].to_string(), &scores) {
+                Some(middle_score) => println!("Part2: {}", middle_score),
+                None => println!("Part2: no incomplete lines"),
+            }
+            SyntaxChecker::navigation()
+        }
+    };
+
+    if has_flag("stats") {
+        print_statistics(args[1].to_string(), &checker, &scores);
+    }
+
+    if has_flag("fix") {
+        print_repairs(args[1].to_string(), &checker);
+    } else if has_flag("diagnose") || option("pairs").is_some() {
+        print_diagnostics(args[1].to_string(), &checker);
+    }
 }
@@ -1,19 +1,101 @@
 use rand::seq::SliceRandom;
-use rand::{Rng};
+use rand::Rng;
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::env;
+use std::hash::{Hash, Hasher};
 
 type Coordinate = (i32, i32);
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edges {
+    Bounded,
+    // Octopuses on opposite edges are adjacent
+    Torus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Neighbourhood {
+    VonNeumann,
+    Moore,
+    // Odd rows are shifted half an octopus to the right
+    Hex,
+}
+
+impl Neighbourhood {
+    fn offsets(&self, position: Coordinate) -> &'static [Coordinate] {
+        match self {
+            Neighbourhood::VonNeumann => &[(0, 1), (0, -1), (1, 0), (-1, 0)],
+            Neighbourhood::Moore => &[
+                (0, 1),
+                (0, -1),
+                (1, 0),
+                (1, 1),
+                (1, -1),
+                (-1, 0),
+                (-1, 1),
+                (-1, -1),
+            ],
+            Neighbourhood::Hex if position.1 % 2 == 0 => {
+                &[(-1, 0), (1, 0), (-1, -1), (0, -1), (-1, 1), (0, 1)]
+            }
+            Neighbourhood::Hex => &[(-1, 0), (1, 0), (0, -1), (1, -1), (0, 1), (1, 1)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rules {
+    edges: Edges,
+    neighbourhood: Neighbourhood,
+    // Octopuses with a higher energy level flash
+    threshold: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            edges: Edges::Bounded,
+            neighbourhood: Neighbourhood::Moore,
+            threshold: 9,
+        }
+    }
+}
+
+impl Rules {
+    // Applies a "name=value" setting, returns false if it isn't a rule
+    fn apply(&mut self, setting: &str) -> bool {
+        let (name, value) = match setting.split_once('=') {
+            Some(setting) => setting,
+            None => return false,
+        };
+
+        match (name, value) {
+            ("edges", "bounded") => self.edges = Edges::Bounded,
+            ("edges", "torus") => self.edges = Edges::Torus,
+            ("neighbourhood", "4") => self.neighbourhood = Neighbourhood::VonNeumann,
+            ("neighbourhood", "8") => self.neighbourhood = Neighbourhood::Moore,
+            ("neighbourhood", "hex") => self.neighbourhood = Neighbourhood::Hex,
+            ("threshold", threshold) => {
+                self.threshold = threshold.parse().expect("Invalid flash threshold")
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
 struct AdjacentRange {
     start: Coordinate,
+    adjacents: &'static [Coordinate],
     index: usize,
 }
 impl AdjacentRange {
-    fn new(position: Coordinate) -> Self {
+    fn new(position: Coordinate, neighbourhood: Neighbourhood) -> Self {
         Self {
             start: position,
+            adjacents: neighbourhood.offsets(position),
             index: 0,
         }
     }
@@ -22,25 +104,13 @@ impl AdjacentRange {
 impl Iterator for AdjacentRange {
     type Item = Coordinate;
     fn next(&mut self) -> Option<Coordinate> {
-        let adjacents = [
-            // (0, 0),
-            (0, 1),
-            (0, -1),
-            (1, 0),
-            (1, 1),
-            (1, -1),
-            (-1, 0),
-            (-1, 1),
-            (-1, -1),
-        ];
-
-        if self.index == adjacents.len() {
+        if self.index == self.adjacents.len() {
             return None;
         }
 
         let next = (
-            self.start.0 + adjacents[self.index].0,
-            self.start.1 + adjacents[self.index].1,
+            self.start.0 + self.adjacents[self.index].0,
+            self.start.1 + self.adjacents[self.index].1,
         );
 
         self.index += 1;
@@ -48,19 +118,170 @@ impl Iterator for AdjacentRange {
     }
 }
 
-fn parse_input(inputfile: String) -> HashMap<Coordinate, u32> {
+// Flashed octopuses, one bit per grid index
+struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    fn new(size: usize) -> Self {
+        Self {
+            words: vec![0; size.div_ceil(64)],
+        }
+    }
+
+    // Returns false if the index was already in the set
+    fn insert(&mut self, index: usize) -> bool {
+        let (word, bit) = (index / 64, 1 << (index % 64));
+        let is_new = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        is_new
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.words[index / 64] & 1 << (index % 64) != 0
+    }
+
+    fn clear(&mut self) {
+        self.words.iter_mut().for_each(|word| *word = 0);
+    }
+}
+
+struct OctopusGrid {
+    width: usize,
+    height: usize,
+    threshold: u32,
+    // Energy levels in row major order
+    energy_levels: Vec<u32>,
+    // Neighbours of every octopus, those of octopus i are at
+    // neighbours[neighbour_starts[i]..neighbour_starts[i + 1]]
+    neighbours: Vec<usize>,
+    neighbour_starts: Vec<usize>,
+    flashed: BitSet,
+    queue: Vec<usize>,
+}
+
+impl OctopusGrid {
+    fn new(width: usize, height: usize, energy_levels: Vec<u32>, rules: &Rules) -> Self {
+        // Wrapping an odd number of rows puts two unshifted rows next to each other
+        if rules.neighbourhood == Neighbourhood::Hex
+            && rules.edges == Edges::Torus
+            && height % 2 == 1
+        {
+            panic!("A hex torus needs an even number of rows, not {}", height);
+        }
+
+        let mut grid = Self {
+            width,
+            height,
+            threshold: rules.threshold,
+            neighbours: vec![],
+            neighbour_starts: vec![0],
+            flashed: BitSet::new(energy_levels.len()),
+            queue: Vec::with_capacity(energy_levels.len()),
+            energy_levels,
+        };
+
+        for index in 0..grid.len() {
+            let neighbours = grid.find_neighbours(index, rules);
+            grid.neighbours.extend(neighbours);
+            grid.neighbour_starts.push(grid.neighbours.len());
+        }
+        grid
+    }
+
+    fn len(&self) -> usize {
+        self.energy_levels.len()
+    }
+
+    fn position(&self, index: usize) -> Coordinate {
+        ((index % self.width) as i32, (index / self.width) as i32)
+    }
+
+    // On a torus only one or two octopuses wide, offsets wrap around to the
+    // same neighbour or to the octopus itself, those count once or not at all
+    fn find_neighbours(&self, index: usize, rules: &Rules) -> Vec<usize> {
+        let (width, height) = (self.width as i32, self.height as i32);
+
+        let mut neighbours = AdjacentRange::new(self.position(index), rules.neighbourhood)
+            .filter_map(|(x, y)| match rules.edges {
+                Edges::Bounded if x < 0 || y < 0 || x >= width || y >= height => None,
+                Edges::Bounded => Some((x, y)),
+                Edges::Torus => Some((x.rem_euclid(width), y.rem_euclid(height))),
+            })
+            .map(|(x, y)| (y * width + x) as usize)
+            .filter(|&neighbour| neighbour != index)
+            .collect::<Vec<usize>>();
+
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn increase_energy(&mut self) {
+        self.energy_levels
+            .iter_mut()
+            .for_each(|energy_level| *energy_level += 1);
+    }
+
+    // Every octopus above the threshold flashes once, and the ones that flashed end at 0
+    fn flash(&mut self) -> u32 {
+        self.flashed.clear();
+        self.queue.clear();
+        for (index, &energy_level) in self.energy_levels.iter().enumerate() {
+            if energy_level > self.threshold {
+                self.queue.push(index);
+            }
+        }
+
+        let mut num_flashes = 0;
+        while let Some(index) = self.queue.pop() {
+            if !self.flashed.insert(index) {
+                continue;
+            }
+            num_flashes += 1;
+
+            for neighbour_index in self.neighbour_starts[index]..self.neighbour_starts[index + 1] {
+                let neighbour = self.neighbours[neighbour_index];
+                self.energy_levels[neighbour] += 1;
+                // Octopuses that were above the threshold at the start are queued already
+                if self.energy_levels[neighbour] == self.threshold + 1 {
+                    self.queue.push(neighbour);
+                }
+            }
+        }
+
+        for (index, energy_level) in self.energy_levels.iter_mut().enumerate() {
+            if self.flashed.contains(index) {
+                *energy_level = 0;
+            }
+        }
+        num_flashes
+    }
+
+    fn step(&mut self) -> u32 {
+        self.increase_energy();
+        self.flash()
+    }
+}
+
+fn parse_input(inputfile: String, rules: &Rules) -> OctopusGrid {
     let contents =
         std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
 
-    let mut grid = HashMap::new();
-
-    contents.lines().enumerate().for_each(|(y, line)| {
-        line.chars().enumerate().for_each(|(x, c)| {
-            let position: Coordinate = (x as i32, y as i32);
-            grid.entry(position).or_insert(c.to_digit(10).unwrap());
+    let rows = contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.chars()
+                .map(|c| c.to_digit(10).unwrap())
+                .collect::<Vec<u32>>()
         })
-    });
-    grid
+        .collect::<Vec<Vec<u32>>>();
+
+    let width = rows.first().map_or(0, |row| row.len());
+    let height = rows.len();
+    OctopusGrid::new(width, height, rows.into_iter().flatten().collect(), rules)
 }
 
 use image::ImageBuffer;
@@ -91,14 +312,8 @@ fn draw_pixel(pixels: &mut Vec<(Coordinate, Color)>, position: Coordinate, color
     pixels.push((position, color));
 }
 
-fn draw_image(image_data: &HashMap<Coordinate, u32>, frame: u32) {
-    let x_min = image_data.iter().map(|(pos, _)| pos.0).min().unwrap();
-    let x_max = image_data.iter().map(|(pos, _)| pos.0).max().unwrap();
-    let y_min = image_data.iter().map(|(pos, _)| pos.1).min().unwrap();
-    let y_max = image_data.iter().map(|(pos, _)| pos.1).max().unwrap();
-    let x_range = (1 + x_max - x_min) as u32;
-    let y_range = (1 + y_max - y_min) as u32;
-    let dimensions: Coordinate = (x_range as i32, y_range as i32);
+fn draw_image(octopus_grid: &OctopusGrid, frame: u32) {
+    let dimensions: Coordinate = (octopus_grid.width as i32, octopus_grid.height as i32);
 
     let border = 2;
     let scale = 4;
@@ -110,18 +325,10 @@ fn draw_image(image_data: &HashMap<Coordinate, u32>, frame: u32) {
     // Translate value to a color from a palette
     let mut pixels = Vec::<(Coordinate, Color)>::new();
 
-    for y in 0..y_range {
-        for x in 0..x_range {
-            let block_pos = (border + x as i32, border + y as i32);
-            let pos = (x as i32, y as i32);
-
-            match image_data.get(&pos) {
-                Some(&value) => draw_pixel(&mut pixels, block_pos, value as usize),
-                None => {
-                    println!("Didn't find {:?}", pos);
-                }
-            }
-        }
+    for (index, &value) in octopus_grid.energy_levels.iter().enumerate() {
+        let (x, y) = octopus_grid.position(index);
+        let block_pos = (border + x, border + y);
+        draw_pixel(&mut pixels, block_pos, value as usize);
     }
 
     let mut img = ImageBuffer::from_fn(real_size.0, real_size.1, |_x, _y| {
@@ -143,100 +350,111 @@ fn draw_image(image_data: &HashMap<Coordinate, u32>, frame: u32) {
         }
     }
 
-    img.save(format!("frames/day11.frame{:05}.png", frame));
-}
-
-fn flash(octopus_grid: &mut HashMap<Coordinate, u32>) -> u32 {
-    let mut queue = octopus_grid
-        .iter()
-        .filter_map(|(position, energy_level)| {
-            if *energy_level > 9 {
-                Some(*position)
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<Coordinate>>();
-
-    let mut visited: Vec<Coordinate> = vec![];
-
-    while !queue.is_empty() {
-        match queue.pop() {
-            Some(position) => {
-                if !visited.contains(&position) {
-                    visited.push(position);
-
-                    for neighbour in AdjacentRange::new(position) {
-                        match octopus_grid.get_mut(&neighbour) {
-                            Some(value) => {
-                                *value += 1;
-                                if *value > 9 && !visited.contains(&neighbour) {
-                                    queue.push(neighbour);
-                                }
-                            }
-                            None => (),
-                        }
-                    }
-                }
-            }
-            None => (),
-        }
-    }
-    let mut num_flashes = 0;
-    octopus_grid.iter_mut().for_each(|(_, energy_level)| {
-        if *energy_level > 9 {
-            num_flashes += 1;
-            *energy_level = 0;
-        }
-    });
-    num_flashes
+    img.save(format!("frames/day11.frame{:05}.png", frame))
+        .expect("Could not save the frame, is there a frames directory?");
 }
 
-fn solve_part1(inputfile: String) -> usize {
-    let mut octopus_grid = parse_input(inputfile);
+fn solve_part1(inputfile: String, rules: &Rules) -> usize {
+    let mut octopus_grid = parse_input(inputfile, rules);
 
     let mut total_flashes = 0;
     for _ in 0..100 {
-        octopus_grid.iter_mut().for_each(|(_, energy_level)| {
-            *energy_level += 1;
-        });
-
-        total_flashes += flash(&mut octopus_grid);
+        total_flashes += octopus_grid.step();
     }
     total_flashes as usize
 }
 
-fn solve_part2(inputfile: String) -> usize {
-    let mut octopus_grid = parse_input(inputfile);
+#[derive(Debug)]
+struct Synchronisation {
+    // The grid repeats the states from step pre_period onwards every period steps
+    pre_period: usize,
+    period: usize,
+    // The first step in which every octopus flashed
+    first_sync: Option<usize>,
+    // Number of flashes in steps 1 up to and including the first repeated state
+    flash_counts: Vec<u32>,
+}
+
+// The simulation is deterministic, so once a state repeats every following
+// step has been seen already. A grid that hasn't synchronised by then never will.
+// Only a fingerprint of every state is kept, a repeated fingerprint is confirmed
+// by simulating the earlier state again from the start.
+fn synchronise(octopus_grid: &mut OctopusGrid) -> Synchronisation {
     let num_octopus = octopus_grid.len() as u32;
+    let start = octopus_grid.energy_levels.clone();
+    let fingerprint = |energy_levels: &[u32]| {
+        let mut hasher = DefaultHasher::new();
+        energy_levels.hash(&mut hasher);
+        hasher.finish()
+    };
 
-    let mut frame = 0;
-    let mut is_synchronized = false;
-    while !is_synchronized {
-        frame += 1;
+    let mut first_seen = HashMap::<u64, usize>::new();
+    first_seen.insert(fingerprint(&start), 0);
 
-        octopus_grid.iter_mut().for_each(|(_, energy_level)| {
-            *energy_level += 1;
-        });
+    let mut first_sync = None;
+    let mut flash_counts = vec![];
 
-        is_synchronized = flash(&mut octopus_grid) == num_octopus;
+    loop {
+        let num_flashes = octopus_grid.step();
+        flash_counts.push(num_flashes);
+        let step = flash_counts.len();
+
+        if num_flashes == num_octopus && first_sync.is_none() {
+            first_sync = Some(step);
+        }
+
+        let state = fingerprint(&octopus_grid.energy_levels);
+        if let Some(&previous_step) = first_seen.get(&state) {
+            let repeated = std::mem::replace(&mut octopus_grid.energy_levels, start.clone());
+            for _ in 0..previous_step {
+                octopus_grid.step();
+            }
+
+            if octopus_grid.energy_levels == repeated {
+                return Synchronisation {
+                    pre_period: previous_step,
+                    period: step - previous_step,
+                    first_sync,
+                    flash_counts,
+                };
+            }
+            octopus_grid.energy_levels = repeated;
+        }
+        first_seen.insert(state, step);
     }
-    frame
 }
 
-fn draw_large_image(width: u32, height: u32) {
+fn solve_part2(synchronisation: &Synchronisation) -> Option<usize> {
+    synchronisation.first_sync
+}
+
+fn print_synchronisation(synchronisation: &Synchronisation) {
+    println!(
+        "pre-period {}, period {}",
+        synchronisation.pre_period, synchronisation.period
+    );
+    match synchronisation.first_sync {
+        Some(step) => println!("first synchronised in step {}", step),
+        None => println!("never synchronises"),
+    }
+    for (step, num_flashes) in synchronisation.flash_counts.iter().enumerate() {
+        println!("step {}: {} flashes", step + 1, num_flashes);
+    }
+}
+
+fn draw_large_image(width: u32, height: u32, rules: &Rules) {
     let mut rng = rand::thread_rng();
 
     let energy_levels = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
 
-    let mut grid = HashMap::new();
-    for y in 0..height {
-        for x in 0..width {
-            let position: Coordinate = (x as i32, y as i32);
-            grid.entry(position)
-                .or_insert(*energy_levels.choose(&mut rng).unwrap());
-        }
-    }
+    let mut grid = OctopusGrid::new(
+        width as usize,
+        height as usize,
+        (0..width * height)
+            .map(|_| *energy_levels.choose(&mut rng).unwrap())
+            .collect(),
+        rules,
+    );
 
     let mut frame = 0;
     draw_image(&grid, frame);
@@ -244,7 +462,7 @@ fn draw_large_image(width: u32, height: u32) {
         println!("frame[{}]", frame);
         frame += 1;
 
-        grid.iter_mut().for_each(|(_, energy_level)| {
+        grid.energy_levels.iter_mut().for_each(|energy_level| {
             *energy_level += 1;
             if rng.gen_bool(1.0 / 3.0) {
                 if rng.gen_bool(1.0 / 3.0) {
@@ -255,7 +473,7 @@ fn draw_large_image(width: u32, height: u32) {
             }
         });
 
-        flash(&mut grid);
+        grid.flash();
 
         draw_image(&grid, frame);
     }
@@ -263,8 +481,28 @@ fn draw_large_image(width: u32, height: u32) {
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    println!("Part1: {}", solve_part1(args[1].to_string()));
-    println!("Part2: {}", solve_part2(args[1].to_string()));
 
-    draw_large_image(240, 240);
+    // e.g. "--edges=torus --neighbourhood=hex --threshold=5"
+    let mut rules = Rules::default();
+    for setting in args.iter().skip(2).filter(|arg| arg.contains('=')) {
+        if !rules.apply(setting.trim_start_matches("--")) {
+            panic!("Unknown rule {}", setting);
+        }
+    }
+
+    println!("Part1: {}", solve_part1(args[1].to_string(), &rules));
+    let synchronisation = synchronise(&mut parse_input(args[1].to_string(), &rules));
+    match solve_part2(&synchronisation) {
+        Some(step) => println!("Part2: {}", step),
+        None => println!("Part2: never synchronises"),
+    }
+
+    if args.iter().any(|arg| arg == "--sync") {
+        print_synchronisation(&synchronisation);
+    }
+
+    // Renders 1000 frames of a noisy grid into frames/
+    if args.iter().any(|arg| arg == "--draw") {
+        draw_large_image(240, 240, &rules);
+    }
 }
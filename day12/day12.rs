@@ -2,180 +2,423 @@ use itertools::Itertools;
 use std::collections::HashMap;
 use std::env;
 
-type Caves = HashMap<String, Vec<String>>;
-
 fn is_lowercase(value: &str) -> bool {
-    match value.find(char::is_lowercase) {
-        Some(_) => true,
-        None => false,
+    value.find(char::is_lowercase).is_some()
+}
+
+// Caves are interned to ids
+#[derive(Debug, Default)]
+struct CaveSystem {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    is_small: Vec<bool>,
+    connections: Vec<Vec<usize>>,
+}
+
+impl CaveSystem {
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+
+        let id = self.names.len();
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        self.is_small.push(is_lowercase(name));
+        self.connections.push(vec![]);
+        id
+    }
+
+    fn connect(&mut self, a: &str, b: &str) {
+        let (a, b) = (self.intern(a), self.intern(b));
+        self.connections[a].push(b);
+        self.connections[b].push(a);
+    }
+
+    fn id(&self, name: &str) -> usize {
+        *self
+            .ids
+            .get(name)
+            .unwrap_or_else(|| panic!("Unknown cave {}", name))
     }
 }
 
-fn parse_input(inputfile: String) -> Caves {
+fn parse_input(inputfile: String) -> CaveSystem {
     let contents =
         std::fs::read_to_string(inputfile).expect("Something went wrong reading the file");
 
-    let mut caves = HashMap::new();
+    let mut caves = CaveSystem::default();
 
     contents.lines().for_each(|line| {
         let mut tokens = line.split('-');
         let name = tokens.next().unwrap();
         let connection = tokens.next().unwrap();
 
-        caves
-            .entry(name.to_string())
-            .and_modify(|e: &mut Vec<String>| e.push(connection.to_string()))
-            .or_insert(vec![connection.to_string()]);
-
-        caves
-            .entry(connection.to_string())
-            .and_modify(|e: &mut Vec<String>| e.push(name.to_string()))
-            .or_insert(vec![name.to_string()]);
+        caves.connect(name, connection);
     });
     caves
 }
 
-fn find_paths(caves: &Caves, start: &str, end: &str) -> usize {
-    let mut queue: Vec<(String, Vec<String>)> = vec![(start.to_string(), vec![])];
-    let mut num_paths = 0;
-
-    let mut paths: Vec<Vec<String>> = vec![];
+#[derive(Debug, Clone)]
+struct VisitPolicy {
+    // Visits per cave that don't use the budget, None if unlimited
+    free_visits: Vec<Option<u32>>,
+    // Extra visits beyond the free ones, shared by all caves
+    extra_visits: u32,
+    // Caves that can't use the extra visits, and caves that can't be entered at all
+    fixed: Vec<bool>,
+    forbidden: Vec<bool>,
+}
 
-    fn could_revisit_node(previous_nodes: &Vec<String>, next_node: &str) -> bool {
-        if previous_nodes.iter().any(|e| e == next_node) {
-            !is_lowercase(next_node)
-        } else {
-            true
+impl VisitPolicy {
+    // Small caves can be visited once, big caves any number of times
+    fn small_caves_once(caves: &CaveSystem) -> Self {
+        Self {
+            free_visits: caves
+                .is_small
+                .iter()
+                .map(|&is_small| if is_small { Some(1) } else { None })
+                .collect(),
+            extra_visits: 0,
+            fixed: vec![false; caves.names.len()],
+            forbidden: vec![false; caves.names.len()],
         }
     }
 
-    while !queue.is_empty() {
-        match queue.pop() {
-            Some((position, mut path)) => {
-                let previous_node = if let Some(node) = path.last() {
-                    Some(node.clone())
-                } else {
-                    None
+    // A single small cave other than the start can be visited twice
+    fn one_small_cave_twice(caves: &CaveSystem, start: &str) -> Self {
+        let mut policy = Self::small_caves_once(caves);
+        policy.extra_visits = 1;
+        policy.fixed[caves.id(start)] = true;
+        policy
+    }
+
+    // Applies a "name=value" setting, e.g. "limit=A:3", "limit=b:*", "extra=2",
+    // "fix=start" or "forbid=c". Returns false if it isn't a policy setting.
+    fn apply(&mut self, caves: &CaveSystem, setting: &str) -> bool {
+        let (name, value) = match setting.split_once('=') {
+            Some(setting) => setting,
+            None => return false,
+        };
+
+        match name {
+            "limit" => {
+                let (cave, limit) = value.split_once(':').expect("Expected cave:limit");
+                self.free_visits[caves.id(cave)] = match limit {
+                    "*" => None,
+                    limit => Some(limit.parse().expect("Invalid visit limit")),
                 };
+            }
+            "extra" => self.extra_visits = value.parse().expect("Invalid extra visits"),
+            "fix" => self.fixed[caves.id(value)] = true,
+            "forbid" => self.forbidden[caves.id(value)] = true,
+            _ => return false,
+        }
+        true
+    }
 
-                path.push(position.clone());
-
-                if position == end {
-                    paths.push(path);
-                } else {
-                    if let Some(backtrack_node) = &previous_node {
-                        if could_revisit_node(&path, &backtrack_node) {
-                            queue.push((backtrack_node.clone(), path.clone()));
-                        }
-                    }
-
-                    if let Some(connections) = caves.get(&position) {
-                        for connection in connections {
-                            if could_revisit_node(&path, &connection) {
-                                queue.push((connection.clone(), path.clone()));
-                            }
-                        }
-                    }
+    fn is_limited(&self, cave: usize) -> bool {
+        self.free_visits[cave].is_some()
+    }
+
+    // The number of extra visits it takes to enter a cave that has been visited
+    // `visits` times, None if it can't be entered
+    fn entry_cost(&self, cave: usize, visits: u32, extra_used: u32) -> Option<u32> {
+        if self.forbidden[cave] {
+            return None;
+        }
+
+        match self.free_visits[cave] {
+            None => Some(0),
+            Some(free_visits) if visits < free_visits => Some(0),
+            Some(_) if !self.fixed[cave] && extra_used < self.extra_visits => Some(1),
+            Some(_) => None,
+        }
+    }
+
+    // Two connected unlimited caves could be passed back and forth forever
+    fn check_finite(&self, caves: &CaveSystem) {
+        let is_unlimited = |cave: usize| !self.is_limited(cave) && !self.forbidden[cave];
+
+        for (cave, connections) in caves.connections.iter().enumerate() {
+            for &connection in connections {
+                if is_unlimited(cave) && is_unlimited(connection) {
+                    panic!(
+                        "Caves {} and {} can be visited without limit, there are infinitely many paths",
+                        caves.names[cave], caves.names[connection]
+                    );
                 }
             }
-            None => (),
         }
     }
+}
 
-    paths.sort();
-    paths.dedup();
+type PathMemo = HashMap<(usize, Vec<u32>, u32), usize>;
 
-    for path in paths.iter() {
-        println!("path: {:?}", path);
+// Number of paths from `cave` to `end`, given how often every limited cave has
+// been visited and how many extra visits have been used
+fn count_paths_from(
+    caves: &CaveSystem,
+    policy: &VisitPolicy,
+    (cave, end): (usize, usize),
+    visits: &mut Vec<u32>,
+    extra_used: u32,
+    memo: &mut PathMemo,
+) -> usize {
+    if cave == end {
+        return 1;
+    }
+    let key = (cave, visits.clone(), extra_used);
+    if let Some(&num_paths) = memo.get(&key) {
+        return num_paths;
     }
-    paths.len()
-}
 
-fn find_paths_small_node_twice(caves: &Caves, start: &str, end: &str) -> usize {
-    let mut queue: Vec<(String, Vec<String>)> = vec![(start.to_string(), vec![])];
     let mut num_paths = 0;
+    for &next in &caves.connections[cave] {
+        let extra = match policy.entry_cost(next, visits[next], extra_used) {
+            Some(extra) => extra,
+            None => continue,
+        };
 
-    let mut paths: Vec<Vec<String>> = vec![];
-
-    fn could_revisit_node(previous_nodes: &Vec<String>, next_node: &str) -> bool {
-        let already_has_visited_a_lowercase_node_twice = previous_nodes
-            .iter()
-            .filter(|node| is_lowercase(node))
-            .sorted()
-            .counts()
-            .iter()
-            .any(|(_, count)| count > &1);
-
-        if previous_nodes.iter().any(|e| e == next_node) {
-            if is_lowercase(next_node) {
-                if next_node == "start" {
-                    false
-                } else {
-                    !already_has_visited_a_lowercase_node_twice
-                }
-            } else {
-                true
-            }
-        } else {
-            true
+        // Visits to unlimited caves don't matter, leaving them out of the memo key
+        let is_limited = policy.is_limited(next);
+        if is_limited {
+            visits[next] += 1;
+        }
+        num_paths += count_paths_from(caves, policy, (next, end), visits, extra_used + extra, memo);
+        if is_limited {
+            visits[next] -= 1;
         }
     }
 
-    while !queue.is_empty() {
-        match queue.pop() {
-            Some((position, mut path)) => {
-                let previous_node = if let Some(node) = path.last() {
-                    Some(node.clone())
-                } else {
-                    None
-                };
+    memo.insert(key, num_paths);
+    num_paths
+}
 
-                path.push(position.clone());
-
-                if position == end {
-                    paths.push(path);
-                } else {
-                    if let Some(backtrack_node) = &previous_node {
-                        if could_revisit_node(&path, &backtrack_node) {
-                            queue.push((backtrack_node.clone(), path.clone()));
-                        }
-                    }
-
-                    if let Some(connections) = caves.get(&position) {
-                        for connection in connections {
-                            if could_revisit_node(&path, &connection) {
-                                queue.push((connection.clone(), path.clone()));
-                            }
-                        }
-                    }
-                }
-            }
-            None => (),
+fn count_paths(caves: &CaveSystem, policy: &VisitPolicy, start: &str, end: &str) -> usize {
+    policy.check_finite(caves);
+    let (start, end) = (caves.id(start), caves.id(end));
+
+    let mut visits = vec![0; caves.names.len()];
+    if policy.is_limited(start) {
+        visits[start] = 1;
+    }
+
+    count_paths_from(
+        caves,
+        policy,
+        (start, end),
+        &mut visits,
+        0,
+        &mut HashMap::new(),
+    )
+}
+
+// Enumerates the paths depth first, one at a time
+struct Paths<'a> {
+    caves: &'a CaveSystem,
+    policy: &'a VisitPolicy,
+    end: usize,
+    // The current path, with the index of the next connection to try from every
+    // cave and the extra visits it took to enter it
+    path: Vec<(usize, usize, u32)>,
+    visits: Vec<u32>,
+    extra_used: u32,
+}
+
+impl<'a> Paths<'a> {
+    fn new(caves: &'a CaveSystem, policy: &'a VisitPolicy, start: &str, end: &str) -> Self {
+        policy.check_finite(caves);
+        let (start, end) = (caves.id(start), caves.id(end));
+
+        let mut visits = vec![0; caves.names.len()];
+        if policy.is_limited(start) {
+            visits[start] = 1;
+        }
+
+        Self {
+            caves,
+            policy,
+            end,
+            path: vec![(start, 0, 0)],
+            visits,
+            extra_used: 0,
         }
     }
+}
+
+impl<'a> Iterator for Paths<'a> {
+    type Item = Vec<&'a str>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (cave, connection_index, extra) = *self.path.last()?;
+
+            if cave == self.end || connection_index == self.caves.connections[cave].len() {
+                self.path.pop();
+                if self.policy.is_limited(cave) {
+                    self.visits[cave] -= 1;
+                }
+                self.extra_used -= extra;
+                continue;
+            }
 
-    paths.sort();
-    paths.dedup();
+            self.path.last_mut().unwrap().1 += 1;
+            let next = self.caves.connections[cave][connection_index];
+            let extra = match self
+                .policy
+                .entry_cost(next, self.visits[next], self.extra_used)
+            {
+                Some(extra) => extra,
+                None => continue,
+            };
 
-    paths.len()
+            // Visits to unlimited caves don't matter, like when counting the paths
+            if self.policy.is_limited(next) {
+                self.visits[next] += 1;
+            }
+            self.extra_used += extra;
+            self.path.push((next, 0, extra));
+
+            if next == self.end {
+                return Some(
+                    self.path
+                        .iter()
+                        .map(|&(cave, _, _)| self.caves.names[cave].as_str())
+                        .collect(),
+                );
+            }
+        }
+    }
 }
 
 fn solve_part1(inputfile: String) -> usize {
     let caves = parse_input(inputfile);
+    let policy = VisitPolicy::small_caves_once(&caves);
 
-    println!("caves: {:?}", caves);
-    find_paths(&caves, "start", "end")
+    count_paths(&caves, &policy, "start", "end")
 }
 
 fn solve_part2(inputfile: String) -> usize {
     let caves = parse_input(inputfile);
+    let policy = VisitPolicy::one_small_cave_twice(&caves, "start");
+
+    count_paths(&caves, &policy, "start", "end")
+}
+
+fn print_paths(caves: &CaveSystem, policy: &VisitPolicy) {
+    for path in Paths::new(caves, policy, "start", "end") {
+        println!("path: {}", path.join(","));
+    }
+}
+
+// How often every tunnel is passed over all the paths, in either direction.
+// Tunnels are keyed by their cave ids, lowest first.
+fn edge_usage(caves: &CaveSystem, policy: &VisitPolicy) -> HashMap<(usize, usize), usize> {
+    let mut usage = HashMap::new();
 
-    println!("caves: {:?}", caves);
-    find_paths_small_node_twice(&caves, "start", "end")
+    for path in Paths::new(caves, policy, "start", "end") {
+        for (a, b) in path.iter().map(|name| caves.id(name)).tuple_windows() {
+            *usage.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    usage
+}
+
+// Big caves are boxes, small caves circles, start and end are filled. With the
+// usage overlay, tunnels are labeled with how often the paths pass them and
+// drawn thicker the more they're used.
+fn write_dot(dotfile: &str, caves: &CaveSystem, usage: Option<&HashMap<(usize, usize), usize>>) {
+    let mut dot = vec!["graph caves {".to_string()];
+
+    for (cave, name) in caves.names.iter().enumerate() {
+        let style = match name.as_str() {
+            "start" => "shape=doublecircle, style=filled, fillcolor=palegreen".to_string(),
+            "end" => "shape=doublecircle, style=filled, fillcolor=lightcoral".to_string(),
+            _ if caves.is_small[cave] => "shape=circle".to_string(),
+            _ => "shape=box, style=filled, fillcolor=lightblue".to_string(),
+        };
+        dot.push(format!("    \"{}\" [{}];", name, style));
+    }
+
+    let max_usage = usage
+        .and_then(|usage| usage.values().max().copied())
+        .unwrap_or(0)
+        .max(1);
+
+    for (cave, connections) in caves.connections.iter().enumerate() {
+        for &connection in connections.iter().filter(|&&connection| cave < connection) {
+            let style = match usage {
+                None => String::new(),
+                Some(usage) => match usage.get(&(cave, connection)) {
+                    None => " [style=dashed, color=gray]".to_string(),
+                    Some(count) => format!(
+                        " [label=\"{}\", penwidth={:.1}]",
+                        count,
+                        1.0 + 7.0 * *count as f64 / max_usage as f64
+                    ),
+                },
+            };
+            dot.push(format!(
+                "    \"{}\" -- \"{}\"{};",
+                caves.names[cave], caves.names[connection], style
+            ));
+        }
+    }
+    dot.push("}".to_string());
+
+    std::fs::write(dotfile, dot.join("\n") + "\n").expect("Something went wrong writing the graph");
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     println!("Part1: {}", solve_part1(args[1].to_string()));
     println!("Part2: {}", solve_part2(args[1].to_string()));
+
+    let caves = parse_input(args[1].to_string());
+
+    // A policy on top of the part 1 rules, e.g. "--limit=A:3 --extra=2 --fix=start --forbid=c"
+    let settings = args
+        .iter()
+        .skip(2)
+        .filter(|arg| arg.contains('=') && !arg.starts_with("--dot="))
+        .collect::<Vec<&String>>();
+    let policy = if settings.is_empty() {
+        None
+    } else {
+        let mut policy = VisitPolicy::small_caves_once(&caves);
+        for setting in settings {
+            if !policy.apply(&caves, setting.trim_start_matches("--")) {
+                panic!("Unknown visit policy setting {}", setting);
+            }
+        }
+        println!(
+            "Paths with policy: {}",
+            count_paths(&caves, &policy, "start", "end")
+        );
+        Some(policy)
+    };
+
+    // "--paths" lists the paths of the policy, or those of part 1, "--paths-twice"
+    // those of part 2
+    if args.iter().any(|arg| arg == "--paths") {
+        match &policy {
+            Some(policy) => print_paths(&caves, policy),
+            None => print_paths(&caves, &VisitPolicy::small_caves_once(&caves)),
+        }
+    }
+    if args.iter().any(|arg| arg == "--paths-twice") {
+        print_paths(&caves, &VisitPolicy::one_small_cave_twice(&caves, "start"));
+    }
+
+    // e.g. "--dot=caves.dot --usage", the usage is counted over the paths of the
+    // policy, or those of part 1
+    if let Some(dotfile) = args.iter().find_map(|arg| arg.strip_prefix("--dot=")) {
+        let usage = if args.iter().any(|arg| arg == "--usage") {
+            let policy = policy.unwrap_or_else(|| VisitPolicy::small_caves_once(&caves));
+            Some(edge_usage(&caves, &policy))
+        } else {
+            None
+        };
+        write_dot(dotfile, &caves, usage.as_ref());
+    }
 }